@@ -0,0 +1,95 @@
+#[macro_use]
+extern crate criterion;
+extern crate bench_defense;
+extern crate nalgebra;
+extern crate pyro;
+extern crate rand;
+
+use bench_defense::*;
+use criterion::{Criterion, ParameterizedBenchmark};
+use nalgebra as na;
+use pyro::*;
+use rand::Rng;
+
+const SIZE: (f32, f32) = (800.0, 600.0);
+const HIT_RADIUS: f32 = 10.0;
+const PROJECTILES: usize = 1000;
+const GRUNT: EnemyStats = EnemyStats {
+    asset: AssetId::Grunt,
+    scale: 1.0,
+    health: 100.0,
+    min_speed: 150.0,
+    max_speed: 200.0,
+    gold: 1,
+    armor: 0.0,
+    resistances: Resistances {
+        standard: 0.0,
+        homing: 0.0,
+        cluster: 0.0,
+        splash: 0.0,
+    },
+    flying: false,
+};
+
+fn setup(enemies: usize) -> World {
+    let mut world = World::new();
+    let mut rng = seeded_rng(0);
+    let level = Level::generated(SIZE.0, SIZE.1);
+    let sides = level.sides();
+    let goal = Goal::new(&level.goal);
+    spawn_random_enemies(&mut world, enemies, &sides, &goal, &GRUNT, &mut rng);
+    let missiles: Vec<_> = (0..PROJECTILES)
+        .map(|_| {
+            let pos = na::Point2::new(rng.gen_range(0.0, SIZE.0), rng.gen_range(0.0, SIZE.1));
+            let dir = na::Vector2::new(1.0, 0.0);
+            create_missile(
+                AssetId::Missile,
+                Position(pos),
+                dir,
+                700.0,
+                Damage::new(1.0),
+                StandardMissile {},
+                SimTime::default(),
+            )
+        }).collect();
+    world.append_components(missiles);
+    world
+}
+
+// The scan `OnProjectileHit::hit` used before the spatial grid
+fn linear_hits(world: &World) -> usize {
+    world
+        .matcher::<All<(Read<StandardMissile>, Read<Position>)>>()
+        .filter(|(_, missile)| {
+            world
+                .matcher::<All<(Read<Enemy>, Read<Position>)>>()
+                .any(|(_, enemy)| na::distance(&missile.0, &enemy.0) <= HIT_RADIUS)
+        }).count()
+}
+
+fn grid_hits(world: &World, grid: &mut SpatialGrid) -> usize {
+    update_enemy_grid(world, grid);
+    world
+        .matcher::<All<(Read<StandardMissile>, Read<Position>)>>()
+        .filter(|(_, missile)| grid.query_radius(missile.0, HIT_RADIUS).next().is_some())
+        .count()
+}
+
+fn collision(c: &mut Criterion) {
+    let benchmark = ParameterizedBenchmark::new(
+        "linear",
+        |b, &enemies| {
+            let world = setup(enemies);
+            b.iter(|| linear_hits(&world))
+        },
+        vec![500, 5_000, 50_000],
+    ).with_function("grid", |b, &enemies| {
+        let world = setup(enemies);
+        let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+        b.iter(|| grid_hits(&world, &mut grid))
+    }).sample_size(10);
+    c.bench("projectile_enemy_collision", benchmark);
+}
+
+criterion_group!(benches, collision);
+criterion_main!(benches);
@@ -0,0 +1,480 @@
+#[macro_use]
+extern crate criterion;
+extern crate bench_defense;
+extern crate nalgebra;
+extern crate pyro;
+extern crate rand;
+
+use bench_defense::*;
+use criterion::{BatchSize, Criterion};
+use nalgebra as na;
+use pyro::*;
+use rand::Rng;
+use std::time::Duration;
+
+const SIZE: (f32, f32) = (800.0, 600.0);
+const COUNTS: [usize; 3] = [100, 1_000, 10_000];
+const GRUNT: EnemyStats = EnemyStats {
+    asset: AssetId::Grunt,
+    scale: 1.0,
+    health: 100.0,
+    min_speed: 150.0,
+    max_speed: 200.0,
+    gold: 1,
+    armor: 0.0,
+    resistances: Resistances {
+        standard: 0.0,
+        homing: 0.0,
+        cluster: 0.0,
+        splash: 0.0,
+    },
+    flying: false,
+};
+
+fn random_point(rng: &mut SimRng) -> na::Point2<f32> {
+    na::Point2::new(rng.gen_range(0.0, SIZE.0), rng.gen_range(0.0, SIZE.1))
+}
+
+fn random_dir(rng: &mut SimRng) -> na::Vector2<f32> {
+    let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    na::Vector2::new(angle.cos(), angle.sin())
+}
+
+fn grunts(world: &mut World, count: usize, rng: &mut SimRng) {
+    let level = Level::generated(SIZE.0, SIZE.1);
+    let sides = level.sides();
+    let goal = Goal::new(&level.goal);
+    spawn_random_enemies(world, count, &sides, &goal, &GRUNT, rng);
+}
+
+fn missiles(world: &mut World, count: usize, created: SimTime, rng: &mut SimRng) {
+    let missiles: Vec<_> = (0..count)
+        .map(|_| {
+            let pos = Position(random_point(rng));
+            let dir = random_dir(rng);
+            create_missile(
+                AssetId::Missile,
+                pos,
+                dir,
+                700.0,
+                Damage::new(1.0),
+                StandardMissile {},
+                created,
+            )
+        }).collect();
+    world.append_components(missiles);
+}
+
+// One projectile on top of every enemy, so that every projectile hits
+fn projectiles_on_enemies<P: Component + Copy>(world: &mut World, projectile: P) {
+    let missiles: Vec<_> = world
+        .matcher::<All<(Read<Enemy>, Read<Position>)>>()
+        .map(|(_, &pos)| {
+            let dir = na::Vector2::new(1.0, 0.0);
+            let now = SimTime::default();
+            let damage = Damage::new(1.0);
+            create_missile(AssetId::Missile, pos, dir, 700.0, damage, projectile, now)
+        }).collect();
+    world.append_components(missiles);
+}
+
+fn bench_over_counts<F>(c: &mut Criterion, id: &str, f: F)
+where
+    F: FnMut(&mut criterion::Bencher, &usize) + 'static,
+{
+    c.bench_function_over_inputs(id, f, COUNTS.to_vec());
+}
+
+fn movement(c: &mut Criterion) {
+    let dt = DeltaTime(1.0 / 60.0);
+    bench_over_counts(c, "move_velocity", move |b, &count| {
+        let mut world = World::new();
+        missiles(&mut world, count, SimTime::default(), &mut seeded_rng(0));
+        b.iter(|| move_velocity(&mut world, dt))
+    });
+    bench_over_counts(c, "follow_flow_fields", move |b, &count| {
+        let level = Level::generated(SIZE.0, SIZE.1);
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let map = level.map(&sides, &goal);
+        let flow = FlowFields::new(&map, &sides, &goal);
+        // Fresh grunts for every batch, so they don't walk off to the goal over the samples
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                world
+            },
+            |world| follow_flow_fields(world, &flow, &map, &goal, dt, SimTime::default()),
+            BatchSize::LargeInput,
+        )
+    });
+    // The grid holds where the enemies spawned, so most of them have neighbours
+    bench_over_counts(c, "steer_crowd", |b, &count| {
+        let config = Config::default().crowd;
+        let mut world = World::new();
+        grunts(&mut world, count, &mut seeded_rng(0));
+        let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+        update_enemy_grid(&world, &mut grid);
+        b.iter(|| steer_crowd(&mut world, &grid, &config))
+    });
+    // Every missile starts without a target, so this includes picking one
+    bench_over_counts(c, "steer_homing", move |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut rng = seeded_rng(0);
+                let mut world = World::new();
+                grunts(&mut world, count, &mut rng);
+                let homing: Vec<_> = (0..count)
+                    .map(|_| {
+                        let projectile = HomingMissile {
+                            target: None,
+                            turn_rate: 6.0,
+                            retarget_range: 200.0,
+                        };
+                        let pos = Position(random_point(&mut rng));
+                        let dir = random_dir(&mut rng);
+                        let now = SimTime::default();
+                        let damage = Damage::new(25.0);
+                        create_missile(AssetId::Missile, pos, dir, 900.0, damage, projectile, now)
+                    }).collect();
+                world.append_components(homing);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| steer_homing(world, dt, grid),
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "update_orientation", |b, &count| {
+        let mut world = World::new();
+        missiles(&mut world, count, SimTime::default(), &mut seeded_rng(0));
+        b.iter(|| update_orientation(&mut world))
+    });
+    bench_over_counts(c, "animate_explosion", move |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut rng = seeded_rng(0);
+                let mut world = World::new();
+                let explosions: Vec<_> = (0..count)
+                    .map(|_| {
+                        let explosion = Explosion {
+                            radius: rng.gen_range(0.0, 25.0),
+                            max_radius: 25.0,
+                        };
+                        (explosion, Position(random_point(&mut rng)))
+                    }).collect();
+                world.append_components(explosions);
+                world
+            },
+            |world| animate_explosion(world, dt),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn spawning(c: &mut Criterion) {
+    bench_over_counts(c, "shoot_at_enemy", |b, &count| {
+        let level = Level::generated(SIZE.0, SIZE.1);
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let map = level.map(&sides, &goal);
+        let flow = FlowFields::new(&map, &sides, &goal);
+        let paths = Paths {
+            flow: &flow,
+            map: &map,
+            goal: &goal,
+        };
+        b.iter_batched_ref(
+            || {
+                let mut rng = seeded_rng(0);
+                let mut world = World::new();
+                let level = Level::generated(SIZE.0, SIZE.1);
+                spawn_towers(&mut world, &Config::default(), &level);
+                grunts(&mut world, count, &mut rng);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid, rng)
+            },
+            |(world, grid, rng)| shoot_at_enemy(world, SimTime::default(), grid, paths, rng),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn removal(c: &mut Criterion) {
+    // Half of the entities expire or die in every case
+    bench_over_counts(c, "kill_entities", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut rng = seeded_rng(0);
+                let mut world = World::new();
+                let later = SimTime(Duration::from_secs(2));
+                missiles(&mut world, count / 2, SimTime::default(), &mut rng);
+                missiles(&mut world, count / 2, later, &mut rng);
+                world
+            },
+            |world| kill_entities(world, SimTime(Duration::from_secs(3))),
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "kill_enemies", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                world
+                    .matcher::<All<(Write<Enemy>,)>>()
+                    .step_by(2)
+                    .for_each(|(enemy,)| enemy.health = 0.0);
+                world
+            },
+            |world| kill_enemies(world),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn projectile_hits(c: &mut Criterion) {
+    bench_over_counts(c, "standard_missile_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                projectiles_on_enemies(&mut world, StandardMissile {});
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let now = SimTime::default();
+                StandardMissileSystem::new().hit(world, grid, &Config::default().projectile, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "homing_missile_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                let homing = HomingMissile {
+                    target: None,
+                    turn_rate: 6.0,
+                    retarget_range: 200.0,
+                };
+                projectiles_on_enemies(&mut world, homing);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let now = SimTime::default();
+                HomingMissileSystem::new().hit(world, grid, &Config::default().projectile, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "spawn_missile_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                let cluster = SpawnMissile {
+                    fragments: 12,
+                    fragment_speed: 150.0,
+                    fragment_damage: 1.0,
+                };
+                projectiles_on_enemies(&mut world, cluster);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let config = Config::default().projectile;
+                let now = SimTime::default();
+                SpawnMissileSystem::new().hit(world, grid, &config, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "cluster_fragment_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                projectiles_on_enemies(&mut world, ClusterFragment {});
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let now = SimTime::default();
+                ClusterFragmentSystem::new().hit(world, grid, &Config::default().projectile, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "splash_missile_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                let splash = SplashMissile {
+                    radius: 60.0,
+                    falloff: 0.5,
+                };
+                projectiles_on_enemies(&mut world, splash);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let now = SimTime::default();
+                SplashMissileSystem::new().hit(world, grid, &Config::default().projectile, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    bench_over_counts(c, "explosion_damage", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut rng = seeded_rng(0);
+                let mut world = World::new();
+                grunts(&mut world, count, &mut rng);
+                let explosions: Vec<_> = (0..count / 10)
+                    .map(|_| {
+                        let explosion = Explosion {
+                            radius: 60.0,
+                            max_radius: 60.0,
+                        };
+                        let area_damage = AreaDamage {
+                            damage: 20.0,
+                            falloff: 0.5,
+                            hit: Vec::new(),
+                        };
+                        (explosion, Position(random_point(&mut rng)), area_damage)
+                    }).collect();
+                world.append_components(explosions);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| explosion_damage(world, grid),
+            BatchSize::LargeInput,
+        )
+    });
+    // Every projectile burns the enemy it hits, on top of the ones already burning
+    bench_over_counts(c, "status_effect_hit", |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                let missiles: Vec<_> = world
+                    .matcher::<All<(Read<Enemy>, Read<Position>)>>()
+                    .map(|(_, &pos)| {
+                        let dir = na::Vector2::new(1.0, 0.0);
+                        let now = SimTime::default();
+                        let damage = Damage {
+                            effect: Some(StatusEffect::Burn {
+                                damage_per_second: 10.0,
+                                duration_ms: 3000,
+                            }),
+                            ..Damage::new(1.0)
+                        };
+                        let projectile = StandardMissile {};
+                        create_missile(AssetId::Missile, pos, dir, 700.0, damage, projectile, now)
+                    }).collect();
+                world.append_components(missiles);
+                let mut grid = SpatialGrid::new(ENEMY_GRID_CELL_SIZE);
+                update_enemy_grid(&world, &mut grid);
+                (world, grid)
+            },
+            |(world, grid)| {
+                let now = SimTime::default();
+                StandardMissileSystem::new().hit(world, grid, &Config::default().projectile, now)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn status_effects(c: &mut Criterion) {
+    let dt = DeltaTime(1.0 / 60.0);
+    // Every enemy burns with the maximum number of stacks
+    bench_over_counts(c, "burn_damage", move |b, &count| {
+        b.iter_batched_ref(
+            || {
+                let mut world = World::new();
+                grunts(&mut world, count, &mut seeded_rng(0));
+                let burn = StatusEffect::Burn {
+                    damage_per_second: 10.0,
+                    duration_ms: 3000,
+                };
+                let max_burn_stacks = Config::default().projectile.max_burn_stacks;
+                world
+                    .matcher::<All<(Write<StatusEffects>,)>>()
+                    .for_each(|(effects,)| {
+                        for _ in 0..max_burn_stacks {
+                            effects.apply(burn, SimTime::default(), max_burn_stacks);
+                        }
+                    });
+                world
+            },
+            |world| burn_damage(world, dt, SimTime::default()),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn flow_fields(c: &mut Criterion) {
+    let level = Level::generated(SIZE.0, SIZE.1);
+    let sides = level.sides();
+    let goal = Goal::new(&level.goal);
+    let map = level.map(&sides, &goal);
+    c.bench_function("flow_fields_new", move |b| {
+        b.iter(|| FlowFields::new(&map, &sides, &goal))
+    });
+    // A tower halfway between the left side and the goal, which reroutes a part of every field
+    c.bench_function("flow_fields_block", move |b| {
+        let level = Level::generated(SIZE.0, SIZE.1);
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let radius = Config::default().placement.radius;
+        let pos = na::Point2::new(SIZE.0 / 4.0, SIZE.1 / 2.0);
+        b.iter_batched_ref(
+            || {
+                let mut map = level.map(&sides, &goal);
+                let flow = FlowFields::new(&map, &sides, &goal);
+                let cells = map.footprint(pos, radius);
+                map.occupy(&cells);
+                (map, flow, cells)
+            },
+            |(map, flow, cells)| flow.block(map, cells),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+// Taken every `checksum_interval` ticks while recording and playing back replays
+fn replay(c: &mut Criterion) {
+    bench_over_counts(c, "checksum", move |b, &count| {
+        let level = Level::generated(SIZE.0, SIZE.1);
+        let mut sim = Simulation::new(Config::default(), level, 0);
+        grunts(&mut sim.world, count, &mut seeded_rng(0));
+        b.iter(|| checksum(&sim))
+    });
+}
+
+criterion_group!(
+    benches,
+    movement,
+    spawning,
+    removal,
+    projectile_hits,
+    status_effects,
+    flow_fields,
+    replay
+);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+extern crate bench_defense;
+use bench_defense::{
+    random_seed, resource_path, run_headless, run_replay, Config, DeltaTime, Level, Replay,
+};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+// Same as `bench_defense --headless`, but builds without the `render` feature and ggez.
+// Usage: headless [--config PATH] [--level PATH] [--frames N] [--dt SECONDS]
+//                 [--size WIDTHxHEIGHT] [--seed N] [--no-crowd]
+//        headless --replay PATH
+// The config and level paths are relative to the `resources` directory, see `resource_path`.
+// `--size` generates the open field in the given size and can't be combined with `--level`.
+// `--replay` plays back a recorded game with its own config, level and seed instead.
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    if let Some(path) = arg_value("--replay") {
+        let replay = Replay::from_file(&PathBuf::from(path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        run_replay(replay);
+        return;
+    }
+    let mut config =
+        Config::load(arg_value("--config").map(String::as_str)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+    if args.iter().any(|arg| arg == "--no-crowd") {
+        config.crowd.enabled = false;
+    }
+    let frames = arg_value("--frames")
+        .map(|f| f.parse().expect("--frames expects an integer"))
+        .unwrap_or(1000);
+    let dt = arg_value("--dt")
+        .map(|dt| dt.parse().expect("--dt expects a number of seconds"))
+        .unwrap_or(1.0 / 60.0);
+    let level = match (arg_value("--level"), arg_value("--size")) {
+        (Some(_), Some(_)) => panic!("--size can't be combined with --level"),
+        (Some(path), None) => Level::from_file(&resource_path(path), &config),
+        (None, Some(size)) => {
+            let mut dims = size
+                .split('x')
+                .map(|d| d.parse::<f32>().expect("--size expects WIDTHxHEIGHT"));
+            match (dims.next(), dims.next()) {
+                (Some(width), Some(height)) => {
+                    let level = Level::generated(width, height);
+                    level.validate(&config).map(|_| level)
+                }
+                _ => panic!("--size expects WIDTHxHEIGHT"),
+            }
+        }
+        (None, None) => {
+            let level = Level::default();
+            level.validate(&config).map(|_| level)
+        }
+    };
+    let level = level.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let seed = arg_value("--seed")
+        .map(|seed| seed.parse().expect("--seed expects an unsigned integer"))
+        .unwrap_or_else(random_seed);
+    run_headless(config, level, frames, DeltaTime(dt), seed);
+}
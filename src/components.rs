@@ -0,0 +1,442 @@
+use nalgebra as na;
+use pyro::{Component, Entity};
+use std::time::Duration;
+
+#[derive(Copy, Clone)]
+pub struct Position(pub na::Point2<f32>);
+#[derive(Copy, Clone)]
+pub struct Velocity(pub na::Vector2<f32>);
+#[derive(Copy, Clone)]
+pub struct Speed(pub f32);
+#[derive(Copy, Clone)]
+pub struct Enemy {
+    pub health: f32,
+    // Health it spawned with
+    pub max_health: f32,
+    // Gold paid out when it is killed
+    pub gold: u32,
+    // Subtracted from every hit, after the resistances
+    pub armor: f32,
+    pub resistances: Resistances,
+    // Flies straight for the goal instead of walking between the waypoint lines
+    pub flying: bool,
+}
+impl Enemy {
+    pub fn take_hit(&mut self, damage: f32, kind: DamageType) {
+        let resisted = damage * (1.0 - self.resistances.against(kind));
+        self.health -= (resisted - self.armor).max(0.0);
+    }
+}
+
+// Where a hit comes from, enemies resist each kind separately. Cluster shells and their
+// fragments deal cluster damage, splash explosions deal splash damage to every enemy they reach.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DamageType {
+    Standard,
+    Homing,
+    Cluster,
+    Splash,
+}
+
+// Fraction of the damage of each type an enemy ignores
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resistances {
+    pub standard: f32,
+    pub homing: f32,
+    pub cluster: f32,
+    pub splash: f32,
+}
+impl Resistances {
+    pub fn against(&self, kind: DamageType) -> f32 {
+        match kind {
+            DamageType::Standard => self.standard,
+            DamageType::Homing => self.homing,
+            DamageType::Cluster => self.cluster,
+            DamageType::Splash => self.splash,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Explosion {
+    pub radius: f32,
+    pub max_radius: f32,
+}
+
+// Damage dealt by a growing explosion, see `explosion_damage`
+pub struct AreaDamage {
+    pub damage: f32,
+    pub falloff: f32,
+    // Enemies that already took damage from this explosion
+    pub hit: Vec<Entity>,
+}
+impl AreaDamage {
+    pub fn at_distance(&self, distance: f32, max_radius: f32) -> f32 {
+        let edge = (distance / max_radius).min(1.0);
+        self.damage * (1.0 - self.falloff * edge)
+    }
+}
+
+// What a projectile does to the enemies it hits, and the pierce and chain state to do it
+#[derive(Clone)]
+pub struct Damage {
+    pub amount: f32,
+    // Further enemies the projectile passes through before it is used up
+    pub pierce: u32,
+    // Further enemies it bounces to once it can't pierce anymore, the nearest one each time
+    pub chain: u32,
+    // Applied to every enemy it hits
+    pub effect: Option<StatusEffect>,
+    // Enemies it already damaged, it never damages the same enemy twice
+    pub hit: Vec<Entity>,
+}
+impl Damage {
+    pub fn new(amount: f32) -> Self {
+        Damage {
+            amount,
+            pierce: 0,
+            chain: 0,
+            effect: None,
+            hit: Vec::new(),
+        }
+    }
+}
+
+pub struct TimeToLive {
+    pub created: SimTime,
+    pub time_until_death: Duration,
+}
+// pyro caps entities at 8 components. Missiles, and enemies as `spawn_random_enemies` builds
+// them, use all of them, so more state for either goes into one of their existing components.
+pub type Missile<Projectile: Component> = (
+    Position,
+    Velocity,
+    Render,
+    Orientation,
+    TimeToLive,
+    Flip,
+    Damage,
+    Projectile,
+);
+pub struct Bullet;
+pub type BulletEntity = (
+    Position,
+    Velocity,
+    Render,
+    Orientation,
+    TimeToLive,
+    Flip,
+    Bullet,
+);
+
+pub struct Render {
+    pub asset: AssetId,
+    pub scale: f32,
+    pub inital_rotation: f32,
+    // Position at the start of the last tick, `draw` interpolates between this and `Position`
+    pub previous_position: na::Point2<f32>,
+}
+
+pub struct MoveTorwards {
+    pub destination: na::Point2<f32>,
+    pub side: usize,
+    // Legs walked so far, enemies head for the goal after `Goal::legs`
+    pub legs: usize,
+    // Direction walked in the last update and the crowd's push added to the next one, see
+    // `steer_crowd`
+    pub heading: na::Vector2<f32>,
+    pub steering: na::Vector2<f32>,
+}
+impl MoveTorwards {
+    pub fn new(destination: na::Point2<f32>, side: usize, legs: usize) -> Self {
+        MoveTorwards {
+            destination,
+            side,
+            legs,
+            heading: na::Vector2::zeros(),
+            steering: na::Vector2::zeros(),
+        }
+    }
+}
+pub struct Orientation(pub f32);
+#[derive(Copy, Clone)]
+pub enum Flip {
+    Left,
+    Right,
+}
+
+// What a tower fires, the parameters of each kind travel with the missile as its projectile
+// component
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProjectileKind {
+    Standard,
+    // Steers towards the enemy it was fired at, turning at most `turn_rate` radians per second.
+    // Picks the nearest enemy within `retarget_range` when its target dies.
+    Homing {
+        turn_rate: f32,
+        retarget_range: f32,
+    },
+    // Splits into `fragments` standard missiles on impact
+    Cluster {
+        fragments: usize,
+        fragment_speed: f32,
+        fragment_damage: f32,
+    },
+    // Leaves an explosion that damages every other enemy it reaches while growing to `radius`.
+    // The damage drops linearly towards the edge, by `falloff` of the full damage at `radius`.
+    Splash {
+        radius: f32,
+        falloff: f32,
+    },
+}
+
+#[derive(Copy, Clone)]
+pub struct Shoot {
+    pub recover: Recover,
+    pub missile_asset: AssetId,
+    pub missile_speed: f32,
+    pub damage: f32,
+    pub pierce: u32,
+    pub chain: u32,
+    pub effect: Option<StatusEffect>,
+    pub projectile: ProjectileKind,
+}
+
+// Index into `Config::towers`
+#[derive(Copy, Clone)]
+pub struct Tower {
+    pub kind: usize,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum UpgradeKind {
+    Damage,
+    FireRate,
+    Range,
+    // Only for towers firing cluster missiles
+    Fragments,
+}
+
+// Levels bought for a tower, its `Shoot`, `Targeting` and `Render` are derived from these and
+// the base stats of its kind, see `apply_upgrades`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Upgrades {
+    pub damage: u32,
+    pub fire_rate: u32,
+    pub range: u32,
+    pub fragments: u32,
+}
+impl Upgrades {
+    pub fn level(&self, kind: UpgradeKind) -> u32 {
+        match kind {
+            UpgradeKind::Damage => self.damage,
+            UpgradeKind::FireRate => self.fire_rate,
+            UpgradeKind::Range => self.range,
+            UpgradeKind::Fragments => self.fragments,
+        }
+    }
+    pub fn level_mut(&mut self, kind: UpgradeKind) -> &mut u32 {
+        match kind {
+            UpgradeKind::Damage => &mut self.damage,
+            UpgradeKind::FireRate => &mut self.fire_rate,
+            UpgradeKind::Range => &mut self.range,
+            UpgradeKind::Fragments => &mut self.fragments,
+        }
+    }
+    pub fn total(&self) -> u32 {
+        self.damage + self.fire_rate + self.range + self.fragments
+    }
+}
+
+// A timed effect a projectile applies to the enemies it hits
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatusEffect {
+    // Multiplies the speed of the enemy by `factor`. Only the strongest slow is active, hitting
+    // an enemy with an equal or stronger one replaces it and restarts the duration.
+    Slow {
+        factor: f32,
+        duration_ms: u64,
+    },
+    // Damage over time. Every hit adds a stack with its own duration, up to
+    // `StatusConfig::max_burn_stacks`, after which the stack closest to expiring is replaced.
+    Burn {
+        damage_per_second: f32,
+        duration_ms: u64,
+    },
+    // Stops the enemy from moving. Doesn't stack, a stun only extends the current one.
+    Stun {
+        duration_ms: u64,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BurnStack {
+    pub damage_per_second: f32,
+    pub until: SimTime,
+}
+
+// Status effects active on an enemy, expired effects are ignored until they are replaced
+#[derive(Clone, Debug)]
+pub struct StatusEffects {
+    pub slow_factor: f32,
+    pub slow_until: SimTime,
+    pub burns: Vec<BurnStack>,
+    pub stun_until: SimTime,
+}
+impl StatusEffects {
+    pub fn none() -> Self {
+        StatusEffects {
+            slow_factor: 1.0,
+            slow_until: SimTime::default(),
+            burns: Vec::new(),
+            stun_until: SimTime::default(),
+        }
+    }
+    pub fn is_slowed(&self, now: SimTime) -> bool {
+        now < self.slow_until
+    }
+    pub fn is_burning(&self, now: SimTime) -> bool {
+        self.burns.iter().any(|burn| now < burn.until)
+    }
+    pub fn is_stunned(&self, now: SimTime) -> bool {
+        now < self.stun_until
+    }
+    // Multiplier for the speed of the enemy, zero while it is stunned
+    pub fn speed_factor(&self, now: SimTime) -> f32 {
+        if self.is_stunned(now) {
+            0.0
+        } else if self.is_slowed(now) {
+            self.slow_factor
+        } else {
+            1.0
+        }
+    }
+    pub fn burn_damage_per_second(&self, now: SimTime) -> f32 {
+        self.burns
+            .iter()
+            .filter(|burn| now < burn.until)
+            .map(|burn| burn.damage_per_second)
+            .sum()
+    }
+    pub fn apply(&mut self, effect: StatusEffect, now: SimTime, max_burn_stacks: usize) {
+        let until = |duration_ms| SimTime(now.0 + Duration::from_millis(duration_ms));
+        match effect {
+            StatusEffect::Slow {
+                factor,
+                duration_ms,
+            } => {
+                if !self.is_slowed(now) || factor <= self.slow_factor {
+                    self.slow_factor = factor;
+                    self.slow_until = until(duration_ms);
+                }
+            }
+            StatusEffect::Burn {
+                damage_per_second,
+                duration_ms,
+            } => {
+                self.burns.retain(|burn| now < burn.until);
+                if self.burns.len() >= max_burn_stacks {
+                    let oldest = self
+                        .burns
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, burn)| burn.until)
+                        .map(|(idx, _)| idx);
+                    match oldest {
+                        Some(idx) => {
+                            self.burns.remove(idx);
+                        }
+                        None => return,
+                    }
+                }
+                self.burns.push(BurnStack {
+                    damage_per_second,
+                    until: until(duration_ms),
+                });
+            }
+            StatusEffect::Stun { duration_ms } => {
+                self.stun_until = self.stun_until.max(until(duration_ms));
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TargetStrategy {
+    Nearest,
+    // The enemy with the fewest legs and then the shortest walk left to the goal
+    FarthestAlongPath,
+    LowestHealth,
+    HighestHealth,
+    Random,
+}
+
+#[derive(Copy, Clone)]
+pub struct Targeting {
+    pub strategy: TargetStrategy,
+    pub range: f32,
+    // Missiles per volley, each one aimed at a different enemy
+    pub volley: usize,
+}
+
+#[derive(Copy, Clone)]
+pub struct DeltaTime(pub f32);
+impl DeltaTime {
+    pub fn to_duration(self) -> Duration {
+        let secs = f64::from(self.0.max(0.0));
+        Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+    }
+}
+
+// Accumulated simulation time, only advanced by the update loop. Stored as a `Duration` so that
+// summing many small steps doesn't drift like a float would.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SimTime(pub Duration);
+impl SimTime {
+    pub fn advance(&mut self, dt: DeltaTime) {
+        self.0 += dt.to_duration();
+    }
+    pub fn duration_since(self, earlier: SimTime) -> Duration {
+        self.0.checked_sub(earlier.0).unwrap_or_default()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Recover {
+    pub last_action: Option<SimTime>,
+    pub recover: Duration,
+}
+impl Recover {
+    pub fn new(recover: Duration) -> Self {
+        Self {
+            last_action: None,
+            recover,
+        }
+    }
+    pub fn is_ready(&self, now: SimTime) -> bool {
+        self.last_action
+            .map(|last_action| now.duration_since(last_action) >= self.recover)
+            .unwrap_or(true)
+    }
+    pub fn action(&mut self, now: SimTime) -> Option<()> {
+        if self.is_ready(now) {
+            self.last_action = Some(now);
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+// Let's not overcomplicate the asset loading system for a simple demo
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum AssetId {
+    Grunt = 0,
+    Missile = 1,
+    SmallMissile = 2,
+    Tower = 3,
+    Explosion = 4,
+    // A single white pixel, for drawing rectangles in a batch
+    Pixel = 5,
+}
@@ -0,0 +1,718 @@
+use crate::{AssetId, ProjectileKind, Resistances, StatusEffect, TargetStrategy};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Config used when none is given, the built-in defaults apply if it's missing
+pub const DEFAULT_CONFIG_PATH: &str = "/config.ron";
+
+// Config and level paths are relative to the `resources` directory, like they are for the ggez
+// filesystem. Outside of cargo they are used as they are.
+pub fn resource_path(path: &str) -> PathBuf {
+    match resources_dir() {
+        Some(dir) => dir.join(path.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
+
+fn resources_dir() -> Option<PathBuf> {
+    env::var_os("CARGO_MANIFEST_DIR").map(|dir| PathBuf::from(dir).join("resources"))
+}
+
+// Everything that used to be hard-coded, see `resources/config.ron` for the documented defaults
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Enemies that reach the goal cost one life each, the game is lost once none are left
+    pub lives: u32,
+    // The game is won once this many waves have been cleared, 0 keeps it going forever
+    pub waves_to_win: usize,
+    pub starting_gold: u32,
+    // Every kind of enemy, waves refer to them by name
+    pub enemies: Vec<EnemyConfig>,
+    // Waves past the end of the level's waves repeat the last one, with the health multiplier
+    // and the enemy counts scaled by this factor for every extra wave
+    pub wave_growth: f32,
+    pub placement: PlacementConfig,
+    // Every kind of tower, the player picks one of these when placing a tower
+    pub towers: Vec<TowerConfig>,
+    pub upgrades: UpgradeConfig,
+    pub projectile: ProjectileConfig,
+    pub crowd: CrowdConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnemyConfig {
+    pub name: String,
+    pub asset: AssetId,
+    pub scale: f32,
+    // Scaled by the `health_multiplier` of the wave
+    pub health: f32,
+    // Scales the speed range of the wave
+    pub speed: f32,
+    // Gold earned for killing one
+    pub gold: u32,
+    // Subtracted from the damage of every hit, after the resistances
+    pub armor: f32,
+    pub resistances: Resistances,
+    // Flies straight for the goal instead of walking between the spawn lines
+    pub flying: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaveConfig {
+    // Spawned in order, one enemy every `spawn_interval_ms`
+    pub groups: Vec<WaveGroup>,
+    pub health_multiplier: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub spawn_interval_ms: u64,
+    // Pause after the previous wave has been cleared
+    pub delay_ms: u64,
+}
+impl WaveConfig {
+    pub fn enemy_count(&self) -> usize {
+        self.groups.iter().map(|group| group.count).sum()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaveGroup {
+    // Name of one of the `enemies`
+    pub enemy: String,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlacementConfig {
+    // Size of a tower's footprint, placed towers can't overlap each other or block the enemies
+    pub radius: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TowerConfig {
+    pub name: String,
+    pub asset: AssetId,
+    pub scale: f32,
+    // Gold it costs to place a tower
+    pub cost: u32,
+    pub recover_ms: u64,
+    pub targeting: TargetStrategy,
+    pub range: f32,
+    pub volley: usize,
+    pub missile_asset: AssetId,
+    pub missile_speed: f32,
+    pub damage: f32,
+    // Enemies a missile passes through after its first hit
+    pub pierce: u32,
+    // Times a missile bounces to the nearest enemy it hasn't hit yet, once it can't pierce
+    pub chain: u32,
+    // Status effect applied to every enemy a missile hits
+    pub effect: Option<StatusEffect>,
+    pub projectile: ProjectileKind,
+}
+impl TowerConfig {
+    pub fn recover(&self) -> Duration {
+        Duration::from_millis(self.recover_ms)
+    }
+}
+
+// Every upgrade level adds the given fraction of the tower's base stat
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpgradeConfig {
+    pub max_level: u32,
+    // Cost of the first level of an upgrade, each further level costs this much more
+    pub cost: u32,
+    pub damage: f32,
+    // Added to the shots per second, the cooldown shrinks accordingly
+    pub fire_rate: f32,
+    pub range: f32,
+    // Extra fragments per level, not a fraction
+    pub fragments: usize,
+    // Growth of the tower sprite per level of any upgrade
+    pub scale: f32,
+}
+impl UpgradeConfig {
+    pub fn cost(&self, level: u32) -> u32 {
+        self.cost * (level + 1)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectileConfig {
+    pub hit_radius: f32,
+    // How far a chaining missile looks for the next enemy
+    pub chain_range: f32,
+    pub explosion_radius: f32,
+    // Burn stacks an enemy can have at once, see `StatusEffect::Burn`
+    pub max_burn_stacks: usize,
+}
+
+// Local steering that keeps walking enemies from piling up on the same pixels
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrowdConfig {
+    // Off to measure what the steering costs
+    pub enabled: bool,
+    // Enemies closer than this push each other apart
+    pub radius: f32,
+    // Neighbours looked at per enemy, in no particular order
+    pub max_neighbours: usize,
+    // Weights of pushing away from and of walking along with the neighbours, relative to the way
+    // to the destination
+    pub separation: f32,
+    pub alignment: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            lives: 20,
+            waves_to_win: 10,
+            starting_gold: 100,
+            enemies: vec![
+                EnemyConfig {
+                    name: "Grunt".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 1.0,
+                    health: 100.0,
+                    speed: 1.0,
+                    gold: 1,
+                    armor: 0.0,
+                    resistances: Resistances::default(),
+                    flying: false,
+                },
+                EnemyConfig {
+                    name: "Runner".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 0.7,
+                    health: 50.0,
+                    speed: 1.6,
+                    gold: 1,
+                    armor: 0.0,
+                    resistances: Resistances::default(),
+                    flying: false,
+                },
+                EnemyConfig {
+                    name: "Brute".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 1.4,
+                    health: 400.0,
+                    speed: 0.6,
+                    gold: 3,
+                    armor: 0.0,
+                    resistances: Resistances::default(),
+                    flying: false,
+                },
+                EnemyConfig {
+                    name: "Knight".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 1.1,
+                    health: 150.0,
+                    speed: 0.9,
+                    gold: 2,
+                    armor: 5.0,
+                    resistances: Resistances {
+                        splash: 0.5,
+                        ..Resistances::default()
+                    },
+                    flying: false,
+                },
+                EnemyConfig {
+                    name: "Flyer".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 0.8,
+                    health: 80.0,
+                    speed: 1.2,
+                    gold: 2,
+                    armor: 0.0,
+                    resistances: Resistances {
+                        cluster: 0.5,
+                        splash: 0.75,
+                        ..Resistances::default()
+                    },
+                    flying: true,
+                },
+                EnemyConfig {
+                    name: "Boss".to_string(),
+                    asset: AssetId::Grunt,
+                    scale: 2.5,
+                    health: 5000.0,
+                    speed: 0.4,
+                    gold: 50,
+                    armor: 10.0,
+                    resistances: Resistances {
+                        standard: 0.25,
+                        homing: 0.25,
+                        cluster: 0.25,
+                        splash: 0.25,
+                    },
+                    flying: false,
+                },
+            ],
+            wave_growth: 1.25,
+            placement: PlacementConfig { radius: 32.0 },
+            towers: vec![
+                TowerConfig {
+                    name: "Rocket".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 0.8,
+                    cost: 50,
+                    recover_ms: 100,
+                    targeting: TargetStrategy::Nearest,
+                    range: 350.0,
+                    volley: 1,
+                    missile_asset: AssetId::Missile,
+                    missile_speed: 900.0,
+                    damage: 25.0,
+                    pierce: 0,
+                    chain: 0,
+                    effect: None,
+                    projectile: ProjectileKind::Standard,
+                },
+                TowerConfig {
+                    name: "Cluster".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 1.0,
+                    cost: 100,
+                    recover_ms: 250,
+                    targeting: TargetStrategy::Nearest,
+                    range: 450.0,
+                    volley: 10,
+                    missile_asset: AssetId::Missile,
+                    missile_speed: 700.0,
+                    damage: 1.0,
+                    pierce: 0,
+                    chain: 0,
+                    effect: None,
+                    projectile: ProjectileKind::Cluster {
+                        fragments: 12,
+                        fragment_speed: 150.0,
+                        fragment_damage: 1.0,
+                    },
+                },
+                TowerConfig {
+                    name: "Splash".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 1.2,
+                    cost: 150,
+                    recover_ms: 800,
+                    targeting: TargetStrategy::HighestHealth,
+                    range: 300.0,
+                    volley: 1,
+                    missile_asset: AssetId::Missile,
+                    missile_speed: 500.0,
+                    damage: 20.0,
+                    pierce: 0,
+                    chain: 0,
+                    effect: Some(StatusEffect::Stun { duration_ms: 250 }),
+                    projectile: ProjectileKind::Splash {
+                        radius: 60.0,
+                        falloff: 0.5,
+                    },
+                },
+                TowerConfig {
+                    name: "Frost".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 0.9,
+                    cost: 75,
+                    recover_ms: 400,
+                    targeting: TargetStrategy::FarthestAlongPath,
+                    range: 300.0,
+                    volley: 3,
+                    missile_asset: AssetId::SmallMissile,
+                    missile_speed: 600.0,
+                    damage: 2.0,
+                    pierce: 2,
+                    chain: 0,
+                    effect: Some(StatusEffect::Slow {
+                        factor: 0.5,
+                        duration_ms: 2000,
+                    }),
+                    projectile: ProjectileKind::Standard,
+                },
+                TowerConfig {
+                    name: "Flame".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 0.9,
+                    cost: 90,
+                    recover_ms: 300,
+                    targeting: TargetStrategy::Nearest,
+                    range: 250.0,
+                    volley: 2,
+                    missile_asset: AssetId::SmallMissile,
+                    missile_speed: 700.0,
+                    damage: 1.0,
+                    pierce: 0,
+                    chain: 1,
+                    effect: Some(StatusEffect::Burn {
+                        damage_per_second: 10.0,
+                        duration_ms: 3000,
+                    }),
+                    projectile: ProjectileKind::Standard,
+                },
+                TowerConfig {
+                    name: "Seeker".to_string(),
+                    asset: AssetId::Tower,
+                    scale: 0.9,
+                    cost: 80,
+                    recover_ms: 300,
+                    targeting: TargetStrategy::FarthestAlongPath,
+                    range: 350.0,
+                    volley: 1,
+                    missile_asset: AssetId::Missile,
+                    missile_speed: 600.0,
+                    damage: 15.0,
+                    pierce: 0,
+                    chain: 2,
+                    effect: None,
+                    projectile: ProjectileKind::Homing {
+                        turn_rate: 6.0,
+                        retarget_range: 200.0,
+                    },
+                },
+            ],
+            upgrades: UpgradeConfig {
+                max_level: 5,
+                cost: 50,
+                damage: 0.25,
+                fire_rate: 0.2,
+                range: 0.1,
+                fragments: 2,
+                scale: 0.05,
+            },
+            projectile: ProjectileConfig {
+                hit_radius: 10.0,
+                chain_range: 150.0,
+                explosion_radius: 25.0,
+                max_burn_stacks: 5,
+            },
+            crowd: CrowdConfig {
+                enabled: true,
+                radius: 16.0,
+                max_neighbours: 8,
+                separation: 0.75,
+                alignment: 0.25,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(msg) => write!(f, "unable to read config: {}", msg),
+            ConfigError::Parse(msg) => write!(f, "unable to parse config: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        let config: Config =
+            ron::de::from_str(source).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(format!("{}: {}", path.display(), err)))?;
+        Config::parse(&source)
+    }
+
+    // Loads the config from disk, the same way `load_config` does through ggez
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        match path {
+            Some(path) => Config::from_file(&resource_path(path)),
+            // Outside of cargo there is no `resources` directory to find the default config in
+            None if resources_dir().is_some() && resource_path(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(&resource_path(DEFAULT_CONFIG_PATH))
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn enemy_kind(&self, name: &str) -> Option<usize> {
+        self.enemies.iter().position(|enemy| enemy.name == name)
+    }
+
+    pub fn tower_kind(&self, name: &str) -> Option<usize> {
+        self.towers.iter().position(|tower| tower.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let check = |valid: bool, msg: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(ConfigError::Invalid(msg.to_string()))
+            }
+        };
+        check(self.lives > 0, "lives must be at least 1")?;
+        check(
+            !self.enemies.is_empty(),
+            "enemies must define at least one enemy",
+        )?;
+        for (idx, enemy) in self.enemies.iter().enumerate() {
+            let check_enemy =
+                |valid: bool, msg: &str| check(valid, &format!("enemies[{}]: {}", idx, msg));
+            check_enemy(
+                self.enemy_kind(&enemy.name) == Some(idx),
+                "name must be unique",
+            )?;
+            check_enemy(enemy.scale > 0.0, "scale must be positive")?;
+            check_enemy(enemy.health > 0.0, "health must be positive")?;
+            check_enemy(enemy.speed > 0.0, "speed must be positive")?;
+            check_enemy(enemy.armor >= 0.0, "armor must not be negative")?;
+            let resistances = &enemy.resistances;
+            check_enemy(
+                [
+                    resistances.standard,
+                    resistances.homing,
+                    resistances.cluster,
+                    resistances.splash,
+                ]
+                .iter()
+                .all(|&resistance| resistance >= 0.0 && resistance <= 1.0),
+                "resistances must be in [0, 1]",
+            )?;
+        }
+        check(self.wave_growth >= 1.0, "wave_growth must be at least 1")?;
+        check(
+            self.placement.radius > 0.0,
+            "placement.radius must be positive",
+        )?;
+        check(
+            !self.towers.is_empty(),
+            "towers must define at least one tower",
+        )?;
+        for (idx, tower) in self.towers.iter().enumerate() {
+            let check_tower =
+                |valid: bool, msg: &str| check(valid, &format!("towers[{}]: {}", idx, msg));
+            check_tower(
+                self.tower_kind(&tower.name) == Some(idx),
+                "name must be unique",
+            )?;
+            check_tower(tower.scale > 0.0, "scale must be positive")?;
+            check_tower(tower.range > 0.0, "range must be positive")?;
+            check_tower(tower.volley > 0, "volley must be at least 1")?;
+            check_tower(tower.missile_speed > 0.0, "missile_speed must be positive")?;
+            check_tower(tower.damage >= 0.0, "damage must not be negative")?;
+            match tower.projectile {
+                ProjectileKind::Standard => {}
+                ProjectileKind::Homing {
+                    turn_rate,
+                    retarget_range,
+                } => {
+                    check_tower(turn_rate >= 0.0, "turn_rate must not be negative")?;
+                    check_tower(retarget_range >= 0.0, "retarget_range must not be negative")?;
+                }
+                ProjectileKind::Cluster {
+                    fragments,
+                    fragment_speed,
+                    fragment_damage,
+                } => {
+                    check_tower(fragments > 0, "fragments must be at least 1")?;
+                    check_tower(fragment_speed > 0.0, "fragment_speed must be positive")?;
+                    check_tower(
+                        fragment_damage >= 0.0,
+                        "fragment_damage must not be negative",
+                    )?;
+                }
+                ProjectileKind::Splash { radius, falloff } => {
+                    check_tower(radius > 0.0, "splash radius must be positive")?;
+                    check_tower(
+                        falloff >= 0.0 && falloff <= 1.0,
+                        "splash falloff must be in [0, 1]",
+                    )?;
+                }
+            }
+            match tower.effect {
+                Some(StatusEffect::Slow { factor, .. }) => {
+                    check_tower(
+                        factor > 0.0 && factor <= 1.0,
+                        "slow factor must be in (0, 1]",
+                    )?;
+                }
+                Some(StatusEffect::Burn {
+                    damage_per_second, ..
+                }) => {
+                    check_tower(
+                        damage_per_second >= 0.0,
+                        "burn damage_per_second must not be negative",
+                    )?;
+                }
+                Some(StatusEffect::Stun { .. }) | None => {}
+            }
+        }
+        check(
+            self.upgrades.damage >= 0.0,
+            "upgrades.damage must not be negative",
+        )?;
+        check(
+            self.upgrades.fire_rate >= 0.0,
+            "upgrades.fire_rate must not be negative",
+        )?;
+        check(
+            self.upgrades.range >= 0.0,
+            "upgrades.range must not be negative",
+        )?;
+        check(
+            self.upgrades.scale >= 0.0,
+            "upgrades.scale must not be negative",
+        )?;
+        check(
+            self.projectile.hit_radius > 0.0,
+            "projectile.hit_radius must be positive",
+        )?;
+        check(
+            self.projectile.chain_range >= 0.0,
+            "projectile.chain_range must not be negative",
+        )?;
+        check(
+            self.projectile.explosion_radius >= 0.0,
+            "projectile.explosion_radius must not be negative",
+        )?;
+        check(
+            self.projectile.max_burn_stacks > 0,
+            "projectile.max_burn_stacks must be at least 1",
+        )?;
+        check(self.crowd.radius > 0.0, "crowd.radius must be positive")?;
+        check(
+            self.crowd.max_neighbours > 0,
+            "crowd.max_neighbours must be at least 1",
+        )?;
+        check(
+            self.crowd.separation >= 0.0,
+            "crowd.separation must not be negative",
+        )?;
+        check(
+            self.crowd.alignment >= 0.0,
+            "crowd.alignment must not be negative",
+        )
+    }
+
+    // Checks the waves of a level against the enemies
+    pub fn validate_waves(&self, waves: &[WaveConfig]) -> Result<(), String> {
+        let check = |valid: bool, msg: &str| if valid { Ok(()) } else { Err(msg.to_string()) };
+        check(!waves.is_empty(), "waves must define at least one wave")?;
+        for (idx, wave) in waves.iter().enumerate() {
+            let check_wave =
+                |valid: bool, msg: &str| check(valid, &format!("waves[{}]: {}", idx, msg));
+            check_wave(wave.enemy_count() > 0, "must spawn at least one enemy")?;
+            check_wave(
+                wave.groups
+                    .iter()
+                    .all(|group| self.enemy_kind(&group.enemy).is_some()),
+                "every group must name one of the enemies",
+            )?;
+            check_wave(
+                wave.health_multiplier > 0.0,
+                "health_multiplier must be positive",
+            )?;
+            check_wave(wave.min_speed > 0.0, "min_speed must be positive")?;
+            check_wave(
+                wave.min_speed < wave.max_speed,
+                "min_speed must be less than max_speed",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Level;
+
+    fn invalid(config: &Config) -> String {
+        config
+            .validate()
+            .expect_err("config should be invalid")
+            .to_string()
+    }
+
+    #[test]
+    fn default_and_shipped_configs_are_valid() {
+        Config::default().validate().unwrap();
+        Config::parse(include_str!("../resources/config.ron")).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut config = Config::default();
+        config.lives = 0;
+        assert_eq!(invalid(&config), "invalid config: lives must be at least 1");
+
+        let mut config = Config::default();
+        let duplicate = config.enemies[0].clone();
+        config.enemies.push(duplicate);
+        let idx = config.enemies.len() - 1;
+        assert_eq!(
+            invalid(&config),
+            format!("invalid config: enemies[{}]: name must be unique", idx)
+        );
+
+        let mut config = Config::default();
+        config.enemies[0].resistances.cluster = 1.5;
+        assert_eq!(
+            invalid(&config),
+            "invalid config: enemies[0]: resistances must be in [0, 1]"
+        );
+
+        let mut config = Config::default();
+        config.towers[0].volley = 0;
+        assert_eq!(
+            invalid(&config),
+            "invalid config: towers[0]: volley must be at least 1"
+        );
+
+        let mut config = Config::default();
+        config.towers.clear();
+        assert_eq!(
+            invalid(&config),
+            "invalid config: towers must define at least one tower"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        match Config::parse("(lives: 10, typo: 1)") {
+            Err(ConfigError::Parse(_)) => {}
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn waves_must_name_known_enemies() {
+        let config = Config::default();
+        let mut waves = Level::default().waves;
+        config.validate_waves(&waves).unwrap();
+        waves[0].groups[0].enemy = "Nobody".to_string();
+        assert_eq!(
+            config.validate_waves(&waves).unwrap_err(),
+            "waves[0]: every group must name one of the enemies"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{Cell, Goal, MoveTorwards, Sides, TileMap};
+use nalgebra as na;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Cost of walking from every cell to the nearest source cell, and the neighbour to step to on
+// the way there. Shared by every enemy heading for the same sources.
+pub struct FlowField {
+    // Integration field, infinite for cells that can't reach a source
+    costs: Vec<f32>,
+    // Direction field as the neighbour with the lowest cost, `None` for sources and for cells
+    // that can't reach one
+    next: Vec<Option<Cell>>,
+}
+
+impl FlowField {
+    pub fn new(map: &TileMap, sources: &[Cell]) -> Self {
+        let mut field = FlowField {
+            costs: vec![std::f32::INFINITY; map.cell_count()],
+            next: vec![None; map.cell_count()],
+        };
+        let mut open = BinaryHeap::new();
+        for &cell in sources {
+            field.costs[map.index(cell)] = 0.0;
+            open.push(OpenCell { cost: 0.0, cell });
+        }
+        field.integrate(map, open);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                field.update_next(map, (x, y));
+            }
+        }
+        field
+    }
+
+    pub fn cost(&self, map: &TileMap, cell: Cell) -> f32 {
+        self.costs[map.index(cell)]
+    }
+
+    // Which way to walk from `pos`, `None` on a source or where no source can be reached
+    pub fn direction(&self, map: &TileMap, pos: na::Point2<f32>) -> Option<na::Vector2<f32>> {
+        let cell = map.cell(pos)?;
+        let next = self.next[map.index(cell)]?;
+        Some((map.center(next) - pos).normalize())
+    }
+
+    // Dijkstra from the cells in `open`, lowering the cost of every cell it reaches cheaper
+    fn integrate(&mut self, map: &TileMap, mut open: BinaryHeap<OpenCell>) {
+        let passable = |cell: Cell| map.is_passable(cell);
+        while let Some(OpenCell { cost, cell }) = open.pop() {
+            if cost > self.costs[map.index(cell)] {
+                continue;
+            }
+            for (next, step) in map.steps(cell, &passable) {
+                let idx = map.index(next);
+                if cost + step < self.costs[idx] {
+                    self.costs[idx] = cost + step;
+                    open.push(OpenCell {
+                        cost: cost + step,
+                        cell: next,
+                    });
+                }
+            }
+        }
+    }
+
+    // Points a passable cell at its cheapest neighbour. An impassable cell, like one a tower was
+    // built on, points at its cheapest passable neighbour, so enemies standing there walk out.
+    fn update_next(&mut self, map: &TileMap, cell: Cell) {
+        let idx = map.index(cell);
+        let passable = |cell: Cell| map.is_passable(cell);
+        self.next[idx] = if self.costs[idx] == 0.0 {
+            None
+        } else if map.is_passable(cell) {
+            cheapest(map, &self.costs, map.steps(cell, &passable))
+        } else {
+            let neighbours = map
+                .adjacent(cell)
+                .filter(|&(next, _, _, _)| map.is_passable(next))
+                .map(|(next, _, _, step)| (next, step));
+            cheapest(map, &self.costs, neighbours)
+        };
+    }
+
+    // Repairs the field after `blocked` became impassable. Only the cells whose way to a source
+    // led through a blocked cell, or cut the corner of one, are integrated again.
+    pub fn block(&mut self, map: &TileMap, blocked: &[Cell]) {
+        let passable = |cell: Cell| map.is_passable(cell);
+        // `None` until known, then whether the way from the cell is broken
+        let mut stale: Vec<Option<bool>> = vec![None; map.cell_count()];
+        for &cell in blocked {
+            stale[map.index(cell)] = Some(true);
+        }
+        let mut chain = Vec::new();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let mut cell = (x, y);
+                // Walk the way towards the sources until its state is known
+                let broken = loop {
+                    if let Some(broken) = stale[map.index(cell)] {
+                        break broken;
+                    }
+                    chain.push(cell);
+                    match self.next[map.index(cell)] {
+                        None => break false,
+                        Some(next) => {
+                            let open = !map.is_passable(cell)
+                                || map.steps(cell, &passable).any(|(step, _)| step == next);
+                            if !open {
+                                break true;
+                            }
+                            cell = next;
+                        }
+                    }
+                };
+                for cell in chain.drain(..) {
+                    stale[map.index(cell)] = Some(broken);
+                }
+            }
+        }
+
+        let is_stale = |cell: Cell| stale[map.index(cell)] == Some(true);
+        let mut open = BinaryHeap::new();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let cell = (x, y);
+                if is_stale(cell) {
+                    self.costs[map.index(cell)] = std::f32::INFINITY;
+                } else if map.adjacent(cell).any(|(next, _, _, _)| is_stale(next)) {
+                    let cost = self.costs[map.index(cell)];
+                    if cost.is_finite() {
+                        open.push(OpenCell { cost, cell });
+                    }
+                }
+            }
+        }
+        self.integrate(map, open);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if is_stale((x, y)) {
+                    self.update_next(map, (x, y));
+                }
+            }
+        }
+    }
+}
+
+// The candidate with the lowest cost to a source after stepping there
+fn cheapest(
+    map: &TileMap,
+    costs: &[f32],
+    candidates: impl Iterator<Item = (Cell, f32)>,
+) -> Option<Cell> {
+    candidates
+        .map(|(next, step)| (next, costs[map.index(next)] + step))
+        .filter(|(_, cost)| cost.is_finite())
+        .min_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap_or(Ordering::Equal))
+        .map(|(next, _)| next)
+}
+
+// One flow field per waypoint line and one for the goal
+pub struct FlowFields {
+    pub sides: Vec<FlowField>,
+    pub goal: FlowField,
+}
+
+impl FlowFields {
+    pub fn new(map: &TileMap, sides: &Sides, goal: &Goal) -> Self {
+        let cells = |points: &[na::Point2<f32>]| -> Vec<Cell> {
+            points.iter().filter_map(|&point| map.cell(point)).collect()
+        };
+        let goal_cells: Vec<_> = map
+            .footprint(goal.position, goal.radius)
+            .into_iter()
+            .chain(map.cell(goal.position))
+            .collect();
+        FlowFields {
+            sides: (0..sides.line_count())
+                .map(|side| FlowField::new(map, &cells(sides.line(side))))
+                .collect(),
+            goal: FlowField::new(map, &goal_cells),
+        }
+    }
+
+    // The field leading to where `target` is heading
+    pub fn towards(&self, target: &MoveTorwards, goal: &Goal) -> &FlowField {
+        if goal.is_heading_here(target) {
+            &self.goal
+        } else {
+            &self.sides[target.side]
+        }
+    }
+
+    pub fn block(&mut self, map: &TileMap, blocked: &[Cell]) {
+        for field in self.sides.iter_mut().chain(Some(&mut self.goal)) {
+            field.block(map, blocked);
+        }
+    }
+}
+
+// The ways enemies take to the goal, for towers that target the enemy farthest along
+#[derive(Copy, Clone)]
+pub struct Paths<'a> {
+    pub flow: &'a FlowFields,
+    pub map: &'a TileMap,
+    pub goal: &'a Goal,
+}
+
+impl<'a> Paths<'a> {
+    // How much of its way an enemy at `pos` has left: the legs after the current one, then the
+    // pixels to the end of the current one. Flying enemies, and walking ones off the fields, head
+    // straight for their destination.
+    pub fn remaining(
+        &self,
+        target: &MoveTorwards,
+        pos: na::Point2<f32>,
+        flying: bool,
+    ) -> (usize, f32) {
+        let legs = self.goal.legs.saturating_sub(target.legs);
+        let cost = self
+            .map
+            .cell(pos)
+            .filter(|_| !flying)
+            .map(|cell| self.flow.towards(target, self.goal).cost(self.map, cell))
+            .filter(|cost| cost.is_finite());
+        let distance = match cost {
+            Some(cost) => cost * self.map.cell_size,
+            None => na::distance(&pos, &target.destination),
+        };
+        (legs, distance)
+    }
+}
+
+// Entry of the Dijkstra open list, ordered so that `BinaryHeap` pops the lowest cost first
+struct OpenCell {
+    cost: f32,
+    cell: Cell,
+}
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+impl Eq for OpenCell {}
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Level;
+
+    // Blocking cells one at a time has to leave every field as if it was built from scratch
+    #[test]
+    fn block_matches_rebuild() {
+        let level = Level::generated(800.0, 600.0);
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let mut map = level.map(&sides, &goal);
+        let mut flow = FlowFields::new(&map, &sides, &goal);
+        // A wall between the left side and the goal that everything reroutes around, then cells
+        // spread over the whole map
+        let wall = (2..map.height - 2).map(|y| (6, y));
+        let spread = (0..60).map(|idx| ((idx * 7) % map.width, (idx * 11) % map.height));
+        let cells: Vec<Cell> = wall.chain(spread).collect();
+        let mut blocked = 0;
+        for cell in cells {
+            if !map.is_buildable(&[cell]) {
+                continue;
+            }
+            map.occupy(&[cell]);
+            flow.block(&map, &[cell]);
+            blocked += 1;
+            let fresh = FlowFields::new(&map, &sides, &goal);
+            let repaired = flow.sides.iter().chain(Some(&flow.goal));
+            for (repaired, fresh) in repaired.zip(fresh.sides.iter().chain(Some(&fresh.goal))) {
+                assert_eq!(
+                    repaired.costs, fresh.costs,
+                    "costs after blocking {:?}",
+                    cell
+                );
+                assert_eq!(
+                    repaired.next, fresh.next,
+                    "directions after blocking {:?}",
+                    cell
+                );
+            }
+        }
+        assert!(blocked > 50);
+    }
+}
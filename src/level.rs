@@ -0,0 +1,380 @@
+use crate::{Config, Goal, Sides, Tile, TileMap, WaveConfig, WaveGroup};
+use nalgebra as na;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Layout of a map and the waves played on it, see `resources/levels/crossing.ron` for a
+// documented example. Tower and enemy kinds are looked up by name in the `Config`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub width: f32,
+    pub height: f32,
+    // Size of a square tile in pixels, enemies find their paths through these
+    pub cell_size: f32,
+    // One string per row of tiles, see `Tile::from_char`. Without rows every tile is buildable.
+    #[serde(default)]
+    pub tiles: Vec<String>,
+    // Enemies spawn on a random point of one line, then walk to the next line in order
+    pub spawns: Vec<SpawnLine>,
+    pub goal: GoalConfig,
+    #[serde(default)]
+    pub towers: Vec<LevelTower>,
+    pub waves: Vec<WaveConfig>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnLine {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+    // Waypoints spread evenly from `from` to `to`, both included
+    pub count: usize,
+}
+impl SpawnLine {
+    pub fn points(&self) -> Vec<na::Point2<f32>> {
+        let from = na::Point2::new(self.from.0, self.from.1);
+        let to = na::Point2::new(self.to.0, self.to.1);
+        if self.count < 2 {
+            return vec![from; self.count];
+        }
+        let step = (to - from) / (self.count - 1) as f32;
+        (0..self.count)
+            .map(|idx| from + step * idx as f32)
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GoalConfig {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    // Trips between the spawn lines an enemy makes before heading for the goal, counting the
+    // one from its spawn point
+    pub legs: usize,
+}
+
+// A tower placed before the game starts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelTower {
+    // Name of one of the towers in the config
+    pub tower: String,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::generated(800.0, 600.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Io(msg) => write!(f, "unable to read level: {}", msg),
+            LevelError::Parse(msg) => write!(f, "unable to parse level: {}", msg),
+            LevelError::Invalid(msg) => write!(f, "invalid level: {}", msg),
+        }
+    }
+}
+
+impl Error for LevelError {}
+
+impl Level {
+    // The open field the game started out with: two spawn lines 100 pixels away from the left
+    // and right border, the goal in the center and a Cluster tower in every corner
+    pub fn generated(width: f32, height: f32) -> Self {
+        const SPACING: f32 = 100.0;
+        const CORNER_OFFSET: f32 = 50.0;
+        let line = |x: f32| SpawnLine {
+            from: (x, SPACING),
+            to: (x, height - SPACING),
+            count: 100,
+        };
+        let corner = |x: f32, y: f32| LevelTower {
+            tower: "Cluster".to_string(),
+            x,
+            y,
+        };
+        let group = |enemy: &str, count: usize| WaveGroup {
+            enemy: enemy.to_string(),
+            count,
+        };
+        Level {
+            width,
+            height,
+            cell_size: 32.0,
+            tiles: Vec::new(),
+            spawns: vec![line(SPACING), line(width - SPACING)],
+            goal: GoalConfig {
+                x: width / 2.0,
+                y: height / 2.0,
+                radius: 40.0,
+                legs: 4,
+            },
+            towers: vec![
+                corner(CORNER_OFFSET, CORNER_OFFSET),
+                corner(width - CORNER_OFFSET, CORNER_OFFSET),
+                corner(width - CORNER_OFFSET, height - CORNER_OFFSET),
+                corner(CORNER_OFFSET, height - CORNER_OFFSET),
+            ],
+            waves: vec![
+                WaveConfig {
+                    groups: vec![group("Grunt", 100)],
+                    health_multiplier: 1.0,
+                    min_speed: 150.0,
+                    max_speed: 200.0,
+                    spawn_interval_ms: 20,
+                    delay_ms: 1000,
+                },
+                WaveConfig {
+                    groups: vec![group("Grunt", 150), group("Runner", 100)],
+                    health_multiplier: 1.5,
+                    min_speed: 150.0,
+                    max_speed: 200.0,
+                    spawn_interval_ms: 10,
+                    delay_ms: 3000,
+                },
+                WaveConfig {
+                    groups: vec![
+                        group("Grunt", 300),
+                        group("Knight", 100),
+                        group("Flyer", 50),
+                        group("Brute", 50),
+                        group("Boss", 1),
+                    ],
+                    health_multiplier: 2.0,
+                    min_speed: 175.0,
+                    max_speed: 225.0,
+                    spawn_interval_ms: 5,
+                    delay_ms: 3000,
+                },
+            ],
+        }
+    }
+
+    pub fn parse(source: &str, config: &Config) -> Result<Level, LevelError> {
+        let level: Level =
+            ron::de::from_str(source).map_err(|err| LevelError::Parse(err.to_string()))?;
+        level.validate(config)?;
+        Ok(level)
+    }
+
+    pub fn from_file(path: &Path, config: &Config) -> Result<Level, LevelError> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| LevelError::Io(format!("{}: {}", path.display(), err)))?;
+        Level::parse(&source, config)
+    }
+
+    pub fn size(&self) -> (f32, f32) {
+        (self.width, self.height)
+    }
+
+    pub fn sides(&self) -> Sides {
+        Sides::new(self.spawns.iter().map(SpawnLine::points).collect())
+    }
+
+    // Row by row, empty if the level doesn't list its tiles
+    pub fn tiles(&self) -> Vec<Tile> {
+        self.tiles
+            .iter()
+            .flat_map(|row| row.chars())
+            .filter_map(Tile::from_char)
+            .collect()
+    }
+
+    // The map before any tower is placed
+    pub fn map(&self, sides: &Sides, goal: &Goal) -> TileMap {
+        TileMap::new(self.size(), self.cell_size, &self.tiles(), sides, goal)
+    }
+
+    pub fn validate(&self, config: &Config) -> Result<(), LevelError> {
+        let check = |valid: bool, msg: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(LevelError::Invalid(msg.to_string()))
+            }
+        };
+        // The far edges belong to no tile, so they are outside as well
+        let inside = |x: f32, y: f32| x >= 0.0 && x < self.width && y >= 0.0 && y < self.height;
+        check(self.width > 0.0, "width must be positive")?;
+        check(self.height > 0.0, "height must be positive")?;
+        check(self.cell_size > 0.0, "cell_size must be positive")?;
+        if !self.tiles.is_empty() {
+            let columns = (self.width / self.cell_size).ceil() as usize;
+            let rows = (self.height / self.cell_size).ceil() as usize;
+            check(
+                self.tiles.len() == rows,
+                &format!(
+                    "tiles must have {} rows, one per {} pixels",
+                    rows, self.cell_size
+                ),
+            )?;
+            for (idx, row) in self.tiles.iter().enumerate() {
+                let check_row =
+                    |valid: bool, msg: &str| check(valid, &format!("tiles[{}]: {}", idx, msg));
+                check_row(
+                    row.chars().count() == columns,
+                    &format!("must have {} tiles", columns),
+                )?;
+                check_row(
+                    row.chars().all(|c| Tile::from_char(c).is_some()),
+                    "tiles must be one of '.', '=' or '#'",
+                )?;
+            }
+        }
+        check(
+            !self.spawns.is_empty(),
+            "spawns must define at least one line",
+        )?;
+        for (idx, line) in self.spawns.iter().enumerate() {
+            let check_line =
+                |valid: bool, msg: &str| check(valid, &format!("spawns[{}]: {}", idx, msg));
+            check_line(line.count > 0, "count must be at least 1")?;
+            check_line(
+                inside(line.from.0, line.from.1) && inside(line.to.0, line.to.1),
+                "must be inside the level",
+            )?;
+        }
+        check(
+            inside(self.goal.x, self.goal.y),
+            "goal must be inside the level",
+        )?;
+        check(self.goal.radius > 0.0, "goal.radius must be positive")?;
+        check(self.goal.legs > 0, "goal.legs must be at least 1")?;
+        for (idx, tower) in self.towers.iter().enumerate() {
+            let check_tower =
+                |valid: bool, msg: &str| check(valid, &format!("towers[{}]: {}", idx, msg));
+            check_tower(
+                config.tower_kind(&tower.tower).is_some(),
+                "tower must name one of the towers in the config",
+            )?;
+            check_tower(inside(tower.x, tower.y), "must be inside the level")?;
+        }
+        config
+            .validate_waves(&self.waves)
+            .map_err(LevelError::Invalid)?;
+
+        let sides = self.sides();
+        let goal = Goal::new(&self.goal);
+        let mut map = self.map(&sides, &goal);
+        let spawn_cells: Vec<_> = sides
+            .points()
+            .filter_map(|&point| map.cell(point))
+            .collect();
+        let goal_cell = map.cell(goal.position);
+        let tiles = self.tiles();
+        check(
+            spawn_cells
+                .iter()
+                .chain(goal_cell.as_ref())
+                .all(|&cell| tiles.get(map.index(cell)) != Some(&Tile::Blocked)),
+            "spawns and the goal can't be on blocked tiles",
+        )?;
+        for (idx, tower) in self.towers.iter().enumerate() {
+            let cells = map.footprint(na::Point2::new(tower.x, tower.y), config.placement.radius);
+            check(
+                map.is_buildable(&cells),
+                &format!("towers[{}]: must stand on buildable tiles", idx),
+            )?;
+            map.occupy(&cells);
+        }
+        check(
+            goal_cell.map_or(false, |goal_cell| {
+                map.reaches_all(goal_cell, &spawn_cells, &[])
+            }),
+            "every spawn point needs a way to the goal",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(level: &Level) -> String {
+        level
+            .validate(&Config::default())
+            .expect_err("level should be invalid")
+            .to_string()
+    }
+
+    #[test]
+    fn generated_and_shipped_levels_are_valid() {
+        let config = Config::parse(include_str!("../resources/config.ron")).unwrap();
+        Level::default().validate(&config).unwrap();
+        Level::generated(300.0, 300.0).validate(&config).unwrap();
+        Level::parse(include_str!("../resources/levels/crossing.ron"), &config).unwrap();
+    }
+
+    #[test]
+    fn spawn_lines_include_both_ends() {
+        let line = SpawnLine {
+            from: (0.0, 0.0),
+            to: (0.0, 30.0),
+            count: 4,
+        };
+        let ys: Vec<f32> = line.points().iter().map(|point| point.y).collect();
+        assert_eq!(ys, [0.0, 10.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn rejects_points_outside() {
+        let mut level = Level::default();
+        level.spawns[0].to.1 = level.height;
+        assert_eq!(
+            invalid(&level),
+            "invalid level: spawns[0]: must be inside the level"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_towers() {
+        let mut level = Level::default();
+        level.towers[0].tower = "Nope".to_string();
+        assert_eq!(
+            invalid(&level),
+            "invalid level: towers[0]: tower must name one of the towers in the config"
+        );
+
+        // On the goal, which is always walkable
+        let mut level = Level::default();
+        level.towers[0].x = level.goal.x;
+        level.towers[0].y = level.goal.y;
+        assert_eq!(
+            invalid(&level),
+            "invalid level: towers[0]: must stand on buildable tiles"
+        );
+    }
+
+    #[test]
+    fn rejects_spawns_cut_off_from_the_goal() {
+        // A wall of blocked tiles between the left spawn line and the goal
+        let mut level = Level::default();
+        let (columns, rows) = (25, 19);
+        level.tiles = (0..rows)
+            .map(|_| {
+                (0..columns)
+                    .map(|x| if x == 6 { '#' } else { '.' })
+                    .collect()
+            }).collect();
+        assert_eq!(
+            invalid(&level),
+            "invalid level: every spawn point needs a way to the goal"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+#[cfg(feature = "render")]
+extern crate ggez;
+#[cfg(feature = "render")]
+extern crate itertools;
+extern crate nalgebra;
+extern crate pyro;
+extern crate rand;
+extern crate ron;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod components;
+pub mod config;
+pub mod flow;
+pub mod level;
+pub mod map;
+pub mod path;
+pub mod projectile;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+pub mod rng;
+pub mod simulation;
+pub mod spatial;
+pub mod spawn;
+pub mod systems;
+pub mod upgrade;
+pub mod wave;
+
+pub use crate::components::*;
+pub use crate::config::*;
+pub use crate::flow::*;
+pub use crate::level::*;
+pub use crate::map::*;
+pub use crate::path::*;
+pub use crate::projectile::*;
+#[cfg(feature = "render")]
+pub use crate::render::*;
+pub use crate::replay::*;
+pub use crate::rng::*;
+pub use crate::simulation::*;
+pub use crate::spatial::*;
+pub use crate::spawn::*;
+pub use crate::systems::*;
+pub use crate::upgrade::*;
+pub use crate::wave::*;
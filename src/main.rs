@@ -1,625 +1,227 @@
+extern crate bench_defense;
 extern crate env_logger;
 extern crate ggez;
-extern crate itertools;
 extern crate pyro;
-extern crate rand;
+use bench_defense::*;
 use ggez::nalgebra as na;
 use ggez::*;
-use itertools::Itertools;
 use pyro::*;
-use rand::{thread_rng, Rng};
 use std::env;
-use std::f32::consts::PI;
 use std::path;
-use std::time::{Duration, Instant, SystemTime};
-
-#[derive(Copy, Clone)]
-pub struct Position(pub na::Point2<f32>);
-#[derive(Copy, Clone)]
-pub struct Velocity(pub na::Vector2<f32>);
-#[derive(Copy, Clone)]
-pub struct Speed(pub f32);
-#[derive(Copy, Clone)]
-pub struct Enemy {
-    pub health: f32,
-}
-
-#[derive(Copy, Clone)]
-pub struct Explosion {
-    pub radius: f32,
-    pub max_radius: f32,
-}
-
-#[derive(Copy, Clone)]
-pub struct Damage(pub f32);
-
-pub struct TimeToLive {
-    pub created: SystemTime,
-    pub time_until_death: Duration,
-}
-pub type Missile<Projectile: Component> = (
-    Position,
-    Velocity,
-    Render,
-    Orientation,
-    TimeToLive,
-    Flip,
-    Damage,
-    Projectile,
-);
-pub struct Bullet;
-pub type BulletEntity = (
-    Position,
-    Velocity,
-    Render,
-    Orientation,
-    TimeToLive,
-    Flip,
-    Bullet,
-);
-
-pub struct Render {
-    pub asset: AssetId,
-    pub scale: f32,
-    pub inital_rotation: f32,
-}
-
-pub struct MoveTorwards {
-    pub destination: na::Point2<f32>,
-    pub side: usize,
-}
-pub struct Orientation(pub f32);
-#[derive(Copy, Clone)]
-pub enum Flip {
-    Left,
-    Right,
-}
-
-#[derive(Copy, Clone)]
-pub struct Shoot {
-    pub recover: Recover,
-}
-
-#[derive(Copy, Clone)]
-pub struct DeltaTime(pub f32);
-
-#[derive(Copy, Clone)]
-pub struct Recover {
-    pub last_action: Option<Instant>,
-    pub recover: Duration,
-}
-impl Recover {
-    pub fn new(recover: Duration) -> Self {
-        Self {
-            last_action: None,
-            recover,
-        }
-    }
-    pub fn action(&mut self) -> Option<()> {
-        if let Some(last_action) = self.last_action {
-            let duration = Instant::now().duration_since(last_action);
-            if duration < self.recover {
-                None
-            } else {
-                self.last_action = Some(Instant::now());
-                Some(())
-            }
-        } else {
-            self.last_action = Some(Instant::now());
-            Some(())
-        }
+use std::process;
+
+// Upper bound of simulation ticks per rendered frame before the simulation falls behind
+const MAX_TICKS_PER_FRAME: usize = 5;
+
+const UPGRADE_KEYS: [(event::Keycode, UpgradeKind, &str); 4] = [
+    (event::Keycode::D, UpgradeKind::Damage, "[D]amage"),
+    (event::Keycode::F, UpgradeKind::FireRate, "[F]ire rate"),
+    (event::Keycode::R, UpgradeKind::Range, "[R]ange"),
+    (event::Keycode::C, UpgradeKind::Fragments, "[C]luster"),
+];
+
+// Number keys select the tower kind that left clicks place
+fn tower_hotkey(keycode: event::Keycode) -> Option<usize> {
+    use ggez::event::Keycode::*;
+    [Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9]
+        .iter()
+        .position(|&key| key == keycode)
+}
+
+fn save_replay(path: &path::Path, replay: &Replay) {
+    match replay.save(path) {
+        Ok(()) => println!("Recorded {} ticks to {}", replay.ticks, path.display()),
+        Err(err) => eprintln!("{}", err),
     }
 }
 
-pub fn shoot_at_enemy(world: &mut World) {
-    let projectiles: Vec<_> = world
-        .matcher::<All<(Read<Position>, Write<Shoot>)>>()
-        .filter_map(|(&spawn_pos, shoot)| shoot.recover.action().map(move |_| spawn_pos))
-        .flat_map(|spawn_pos| {
-            world
-                .matcher::<All<(Read<Position>, Read<Enemy>)>>()
-                .take(10)
-                .map(move |(&target_pos, _)| {
-                    let dir = (target_pos.0 - spawn_pos.0).normalize();
-                    let offset = dir * 30.0;
-                    let new_pos = Position(spawn_pos.0 + offset);
-                    create_missile(AssetId::Missile, new_pos, dir, 700.0, SpawnMissile {})
-                })
-        }).collect();
-    world.append_components(projectiles);
-}
-
-pub fn update_orientation(world: &mut World) {
-    world
-        .matcher::<All<(Read<Velocity>, Write<Orientation>)>>()
-        .for_each(|(vel, orientation)| {
-            let dir = vel.0.normalize();
-            let mut angle = na::angle(&dir, &na::Vector2::new(1.0, 0.0));
-            if dir.y < 0.0 {
-                angle = -angle;
-            }
-            orientation.0 = angle;
-        });
-}
-
-pub fn move_torwards(world: &mut World, dt: DeltaTime) {
-    world
-        .matcher::<All<(
-            Write<Position>,
-            Read<MoveTorwards>,
-            Read<Speed>,
-            Write<Flip>,
-        )>>().for_each(|(pos, target, speed, flip)| {
-            let dir = (target.destination - pos.0).normalize();
-            pos.0 += dir * speed.0 * dt.0;
-            let angle = na::angle(&na::Vector2::new(1.0, 0.0), &dir);
-
-            *flip = if angle > PI / 2.0 {
-                Flip::Left
-            } else {
-                Flip::Right
-            };
-        });
-}
-
-pub fn update_destination(world: &mut World, sides: &Sides) {
-    world
-        .matcher::<All<(Read<Position>, Write<MoveTorwards>)>>()
-        .for_each(|(pos, target)| {
-            let distance = na::distance(&target.destination, &pos.0);
-            if distance <= 1.0 {
-                *target = sides.get_random_point(target.side);
-            }
-        });
-}
-
-pub fn create_radial_missiles<Projectile: Component + Copy>(
-    pos: Position,
-    speed: f32,
-    offset: f32,
-    count: usize,
-    projectile: Projectile,
-) -> impl Iterator<Item = Missile<Projectile>> {
-    let step_size = 2.0 * PI / count as f32;
-    (0..count)
-        .scan(0.0, move |acc, _| {
-            *acc += step_size;
-            Some(*acc)
-        }).map(move |angle| {
-            let x = offset * f32::cos(angle);
-            let y = offset * f32::sin(angle);
-            let dir = na::Vector2::new(x, y).normalize();
-            create_missile(AssetId::SmallMissile, pos, dir, speed, projectile)
-        })
+struct MainState {
+    sim: Simulation,
+    // Index into `Config::towers` of the tower kind to place
+    selected_tower: usize,
+    // Placed tower that the upgrade keys apply to
+    selected: Option<Entity>,
+    // Why the last placement or upgrade failed, shown until the next one
+    error: Option<String>,
+    timestep: FixedTimestep,
+    profiler: Profiler,
+    // Where the game being played is recorded to, written when it restarts and on exit
+    recording: Option<(path::PathBuf, Replay)>,
+    // Set when watching a replay, player inputs are ignored then
+    playback: Option<Playback>,
+    // First checksum of the replay that the game didn't match
+    desync: Option<Desync>,
+    store: AssetStore,
+    font: graphics::Font,
 }
 
-pub fn kill_enemies(world: &mut World) {
-    let dead_enemies: Vec<_> = world
-        .matcher_with_entities::<All<(Read<Enemy>,)>>()
-        .filter_map(|(entity, (enemy,))| {
-            if enemy.health <= 0.0 {
-                Some(entity)
-            } else {
-                None
+impl MainState {
+    fn new(
+        ctx: &mut Context,
+        config_path: Option<&str>,
+        level_path: Option<&str>,
+        seed: u64,
+        tick_rate: f32,
+        crowd: bool,
+    ) -> GameResult<MainState> {
+        let mut config = load_config(ctx, config_path)?;
+        config.crowd.enabled &= crowd;
+        let level = match level_path {
+            Some(path) => load_level(ctx, path, &config)?,
+            None => {
+                let level = Level::default();
+                level
+                    .validate(&config)
+                    .map_err(|err| GameError::ConfigError(format!("default level: {}", err)))?;
+                level
             }
-        }).collect();
-    world.remove_entities(dead_enemies);
-}
-
-pub trait OnProjectileHit {
-    type Projectile: Component + Sized;
-    fn finish(&mut self, _world: &mut World) {}
-    fn on_projectile_hit(&mut self, pos: Position, projectile: &Self::Projectile);
-    fn hit(&mut self, world: &mut World) {
-        const HIT_RADIUS: f32 = 10.0;
-        let mut explosions = Vec::new();
-        let mut entities = Vec::new();
-        world
-            .matcher_with_entities::<All<(Read<Self::Projectile>, Read<Position>, Read<Damage>)>>()
-            .for_each(|(entity, (projectile, &missile, damage))| {
-                let colliding_enemy = world
-                    .matcher::<All<(Write<Enemy>, Read<Position>)>>()
-                    .find_map(|(enemy, enemy_pos)| {
-                        if na::distance(&missile.0, &enemy_pos.0) <= HIT_RADIUS {
-                            Some(enemy)
-                        } else {
-                            None
-                        }
-                    });
-
-                if let Some(enemy) = colliding_enemy {
-                    enemy.health -= damage.0;
-                    self.on_projectile_hit(missile, projectile);
-                    explosions.push((
-                        Explosion {
-                            radius: 0.0,
-                            max_radius: 25.0,
-                        },
-                        missile,
-                    ));
-                    entities.push(entity);
-                }
-            });
-        world.append_components(explosions);
-        world.remove_entities(entities);
-        self.finish(world);
+        };
+        apply_window_config(ctx, &level)?;
+        let store = AssetStore::load(ctx).expect("Unable to load assets");
+        let font = graphics::Font::new(ctx, "/DejaVuSerif.ttf", 18)?;
+        let s = MainState {
+            selected_tower: level
+                .towers
+                .first()
+                .and_then(|tower| config.tower_kind(&tower.tower))
+                .unwrap_or(0),
+            sim: Simulation::new(config, level, seed),
+            selected: None,
+            error: None,
+            timestep: FixedTimestep::new(tick_rate, MAX_TICKS_PER_FRAME),
+            profiler: Profiler::new(),
+            recording: None,
+            playback: None,
+            desync: None,
+            store,
+            font,
+        };
+        Ok(s)
     }
-}
 
-#[derive(Copy, Clone)]
-pub struct StandardMissile;
-pub struct StandardMissileSystem;
-impl StandardMissileSystem {
-    pub fn new() -> Self {
-        StandardMissileSystem {}
+    // Starts over with the same config and level and a fresh seed, or watches the replay again
+    fn restart(&mut self) {
+        self.selected = None;
+        self.error = None;
+        self.desync = None;
+        if let Some(playback) = self.playback.take() {
+            self.play(playback.replay);
+            return;
+        }
+        let seed = random_seed();
+        println!("Seed: {}", seed);
+        self.sim = Simulation::new(self.sim.config.clone(), self.sim.level.clone(), seed);
+        if let Some((path, replay)) = self.recording.take() {
+            save_replay(&path, &replay);
+            self.record(path, replay.checksum_interval);
+        }
     }
-}
-impl OnProjectileHit for StandardMissileSystem {
-    type Projectile = StandardMissile;
-    fn on_projectile_hit(&mut self, _pos: Position, _projectile: &Self::Projectile) {}
-}
 
-#[derive(Copy, Clone)]
-pub struct SpawnMissile;
-pub struct SpawnMissileSystem {
-    spawn: Vec<Missile<StandardMissile>>,
-}
-impl SpawnMissileSystem {
-    pub fn new() -> Self {
-        Self { spawn: Vec::new() }
-    }
-}
-impl OnProjectileHit for SpawnMissileSystem {
-    type Projectile = SpawnMissile;
-    fn on_projectile_hit(&mut self, pos: Position, _projectile: &Self::Projectile) {
-        let missiles = create_radial_missiles(pos, 150.0, 15.0, 12, StandardMissile {});
-        self.spawn.extend(missiles);
+    fn record(&mut self, path: path::PathBuf, checksum_interval: u64) {
+        let replay = Replay::record(&self.sim, self.timestep.tick, checksum_interval);
+        self.recording = Some((path, replay));
     }
-    fn finish(&mut self, world: &mut World) {
-        let spawn = self.spawn.drain(0..);
-        world.append_components(spawn);
-    }
-}
 
-pub fn animate_explosion(world: &mut World, dt: DeltaTime) {
-    const EXPANSION_SPEED: f32 = 25.0;
-    world
-        .matcher::<All<(Write<Explosion>, Read<Position>)>>()
-        .for_each(|(explosion, _)| {
-            explosion.radius += EXPANSION_SPEED * dt.0;
-        });
-    let entities: Vec<_> = world
-        .matcher_with_entities::<All<(Write<Explosion>,)>>()
-        .filter_map(|(entity, (explosion,))| {
-            if explosion.radius >= explosion.max_radius {
-                Some(entity)
-            } else {
-                None
-            }
-        }).collect();
-    world.remove_entities(entities);
-}
-pub fn draw_explosion(store: &AssetStore, ctx: &mut Context, world: &mut World) {
-    let circle = &store.assets[AssetId::Explosion as usize];
-    let mut batch = graphics::spritebatch::SpriteBatch::new(circle.image.clone());
-    world
-        .matcher::<All<(Read<Explosion>, Read<Position>)>>()
-        .for_each(|(explosion, pos)| {
-            let alpha = 1.0 - explosion.radius * 255.0 / explosion.max_radius;
-            let param = graphics::DrawParam {
-                dest: graphics::Point2::new(pos.0.x, pos.0.y),
-                rotation: 0.0,
-                offset: na::Point2::new(0.5, 0.5),
-                scale: na::Point2::new(circle.scale, circle.scale) * explosion.radius,
-                // ggez seems to ingore color in batches, but it respects the alpha value
-                color: Some(graphics::Color::from_rgba(255, 0, 0, alpha as u8)),
-                ..Default::default()
-            };
-            batch.add(param);
-        });
-    graphics::draw_ex(ctx, &batch, graphics::DrawParam::default());
-}
-pub fn draw(store: &AssetStore, world: &mut World, ctx: &mut Context) -> GameResult<()> {
-    let submisson = world
-        .matcher::<All<(Read<Position>, Read<Orientation>, Read<Flip>, Read<Render>)>>()
-        .sorted_by(|(_, _, _, left), (_, _, _, right)| Ord::cmp(&left.asset, &right.asset))
-        .into_iter()
-        .group_by(|(_, _, _, render)| render.asset);
-
-    for (key, group) in &submisson {
-        let asset = &store.assets[key as usize];
-        let image = asset.image.clone();
-        let mut batch = graphics::spritebatch::SpriteBatch::new(image);
-        for (pos, orientation, flip, render) in group {
-            let scale_y = match flip {
-                Flip::Left => 1.0,
-                Flip::Right => -1.0,
-            };
-            let param = graphics::DrawParam {
-                dest: graphics::Point2::new(pos.0.x, pos.0.y),
-                rotation: orientation.0 + asset.rotation,
-                offset: na::Point2::new(0.5, 0.5),
-                scale: na::Point2::new(render.scale * scale_y, render.scale) * asset.scale,
-                ..Default::default()
-            };
-            batch.add(param);
+    fn save_recording(&self) {
+        if let Some((path, replay)) = &self.recording {
+            save_replay(path, replay);
         }
-        graphics::draw_ex(ctx, &batch, graphics::DrawParam::default());
     }
-    Ok(())
-}
-pub fn create_bullet(location: Position, target: Position, speed: f32) -> BulletEntity {
-    let dir = (target.0 - location.0).normalize() * speed;
-    (
-        location,
-        Velocity(dir),
-        Render {
-            asset: AssetId::Missile,
-            scale: 0.2,
-            inital_rotation: PI / 2.0,
-        },
-        Orientation(0.0),
-        TimeToLive {
-            created: SystemTime::now(),
-            time_until_death: Duration::from_secs(3),
-        },
-        Flip::Right,
-        Bullet {},
-    )
-}
-pub fn create_missile<Projectile: Component>(
-    asset: AssetId,
-    location: Position,
-    dir: na::Vector2<f32>,
-    speed: f32,
-    projectile: Projectile,
-) -> Missile<Projectile> {
-    (
-        location,
-        Velocity(dir * speed),
-        Render {
-            asset,
-            scale: 1.0,
-            inital_rotation: PI / 2.0,
-        },
-        Orientation(0.0),
-        TimeToLive {
-            created: SystemTime::now(),
-            time_until_death: Duration::from_secs(3),
-        },
-        Flip::Right,
-        Damage(1.0),
-        projectile,
-    )
-}
-
-pub fn kill_entities(world: &mut World) {
-    let entities: Vec<_> = world
-        .matcher_with_entities::<All<(Read<TimeToLive>,)>>()
-        .filter_map(|(entity, (time,))| {
-            let now = SystemTime::now();
-            if now.duration_since(time.created).unwrap() >= time.time_until_death {
-                Some(entity)
-            } else {
-                None
-            }
-        }).collect();
-    world.remove_entities(entities);
-}
-
-pub fn move_velocity(world: &mut World, dt: DeltaTime) {
-    world
-        .matcher::<All<(Write<Position>, Read<Velocity>)>>()
-        .for_each(|(pos, vel)| {
-            pos.0 += vel.0 * dt.0;
-        })
-}
-
-pub fn spawn_random_grunts(world: &mut World, count: usize, sides: &Sides) {
-    let ships = (0..count).map(|_| {
-        let move_torwards = sides.get_random_point(sides.get_random_side());
-        (
-            Position(sides.get_random_point(move_torwards.side).destination),
-            move_torwards,
-            Orientation(0.0),
-            Speed(thread_rng().gen_range(150.0, 200.0)),
-            Render {
-                asset: AssetId::Grunt,
-                scale: 1.0,
-                inital_rotation: 0.0,
-            },
-            Flip::Right,
-            Enemy { health: 100.0 },
-        )
-    });
-    world.append_components(ships);
-}
-
-// Let's not overcomplicate the asset loading system for a simple demo
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
-pub enum AssetId {
-    Grunt = 0,
-    Missile = 1,
-    SmallMissile = 2,
-    Tower = 3,
-    Explosion = 4,
-}
 
-pub struct AssetSettings {
-    pub scale: f32,
-    pub rotation: f32,
-    pub image: graphics::Image,
-}
-
-pub struct AssetStore {
-    assets: Vec<AssetSettings>,
-}
-
-impl AssetStore {
-    pub fn load(ctx: &mut Context) -> GameResult<AssetStore> {
-        let assets = vec![
-            AssetSettings {
-                scale: 1.0,
-                rotation: 0.0,
-                image: graphics::Image::new(ctx, "/grunt.png")?,
-            },
-            AssetSettings {
-                scale: 1.0,
-                rotation: PI / 2.0,
-                image: graphics::Image::new(ctx, "/missile1.png")?,
-            },
-            AssetSettings {
-                scale: 0.7,
-                rotation: PI / 2.0,
-                image: graphics::Image::new(ctx, "/missile2.png")?,
-            },
-            AssetSettings {
-                scale: 1.0,
-                rotation: 0.0,
-                image: graphics::Image::new(ctx, "/tower.png")?,
-            },
-            AssetSettings {
-                scale: 0.01,
-                rotation: 0.0,
-                image: graphics::Image::new(ctx, "/explosion.png")?,
-            },
-        ];
-        Ok(AssetStore { assets })
+    // Replaces the game with a replay, ticking at the rate it was recorded with
+    fn play(&mut self, replay: Replay) {
+        let playback = Playback::new(replay);
+        println!("Seed: {}", playback.replay.seed);
+        self.sim = playback.simulation();
+        self.timestep = FixedTimestep::new(1.0 / playback.replay.dt, MAX_TICKS_PER_FRAME);
+        self.playback = Some(playback);
     }
-}
 
-pub struct Sides {
-    waypoints: [Waypoints; 2],
-}
-impl Sides {
-    pub fn new((width, height): (f32, f32), spacing: f32, count: usize) -> Sides {
-        let left = Waypoints::line((spacing, height), spacing, count);
-        let right = Waypoints::line((width - spacing, height), spacing, count);
-        Sides {
-            waypoints: [left, right],
-        }
+    // Over when the game is won or lost, or the replay ran out of ticks
+    fn is_over(&self) -> bool {
+        let finished = self
+            .playback
+            .as_ref()
+            .map_or(false, |playback| playback.is_finished(&self.sim));
+        finished || self.sim.state != GameState::Running
     }
 
-    pub fn get_random_side(&self) -> usize {
-        thread_rng().gen_range(0, self.waypoints.len())
-    }
-    pub fn get_random_point(&self, previous_side: usize) -> MoveTorwards {
-        let next_side = (previous_side + 1) % self.waypoints.len();
-        MoveTorwards {
-            destination: self.waypoints[next_side].get_random_point(),
-            side: next_side,
+    // Everything the player does to the game goes through here so recordings miss nothing
+    fn apply_input(&mut self, input: Input) {
+        if self.playback.is_some() {
+            return;
+        }
+        match input.apply(&mut self.sim) {
+            Ok(()) => {
+                self.error = None;
+                if let Some((_, replay)) = &mut self.recording {
+                    replay.record_input(&self.sim, input);
+                }
+            }
+            Err(err) => self.error = Some(err.to_string()),
         }
     }
-}
 
-pub struct Waypoints {
-    pub points: Vec<na::Point2<f32>>,
-}
-
-impl Waypoints {
-    pub fn line((offset, height): (f32, f32), spacing: f32, count: usize) -> Self {
-        let adjusted_height = height - spacing;
-        let step = (adjusted_height - spacing) / count as f32;
-        let create_waypoints = |offset: f32| {
-            (0..count).scan(na::Point2::new(0.0f32, spacing), move |state, _| {
-                *state += na::Vector2::new(0.0, step);
-                state.x = offset;
-                Some(*state)
-            })
-        };
-        let mut points = Vec::new();
-        points.extend(create_waypoints(offset));
-        Waypoints { points }
-    }
-    pub fn get_random_point(&self) -> na::Point2<f32> {
-        let index: usize = thread_rng().gen_range(0, self.points.len());
-        self.points[index]
-    }
-}
-pub struct EnemySpawner {
-    pub enemies_to_spawn: usize,
-}
-impl EnemySpawner {
-    pub fn spawn_enemies(&mut self, world: &mut World, sides: &Sides) {
-        let living_enemies = world.matcher::<All<(Read<Enemy>,)>>().count();
-        if living_enemies > 0 {
-            return;
+    fn upgrade_selected(&mut self, upgrade: UpgradeKind) {
+        let pos = self
+            .selected
+            .and_then(|tower| self.sim.world.get_component::<Position>(tower))
+            .map(|pos| pos.0);
+        match pos {
+            Some(pos) => self.apply_input(Input::UpgradeTower {
+                x: pos.x,
+                y: pos.y,
+                upgrade,
+            }),
+            None => {
+                let err = InputError::Upgrade(UpgradeError::NoTower);
+                self.error = Some(err.to_string());
+            }
         }
-        spawn_random_grunts(world, self.enemies_to_spawn, &sides);
     }
-}
 
-struct MainState {
-    world: World,
-    sides: Sides,
-    store: AssetStore,
-    font: graphics::Font,
-    spawner: EnemySpawner,
-}
-
-pub fn spawn_towers(world: &mut World, (width, height): (f32, f32), offset: f32) {
-    let spawn_points = [
-        na::Point2::new(0.0 + offset, 0.0 + offset),
-        na::Point2::new(width - offset, 0.0 + offset),
-        na::Point2::new(width - offset, height - offset),
-        na::Point2::new(0.0 + offset, height - offset),
-    ];
-    let towers = spawn_points.iter().map(|&pos| {
-        (
-            Position(pos),
-            Render {
-                asset: AssetId::Tower,
-                scale: 1.0,
-                inital_rotation: 0.0,
-            },
-            Shoot {
-                recover: Recover::new(Duration::from_millis(250)),
-            },
-            Orientation(0.0),
-            Flip::Right,
-        )
-    });
-    world.append_components(towers);
-}
-impl MainState {
-    fn new(ctx: &mut Context) -> GameResult<MainState> {
-        let mut world = World::new();
-        let size = (
-            ctx.conf.window_mode.width as f32,
-            ctx.conf.window_mode.height as f32,
-        );
-        let store = AssetStore::load(ctx).expect("Unable to load assets");
-        let sides = Sides::new(size, 100.0, 100);
-        let font = graphics::Font::new(ctx, "/DejaVuSerif.ttf", 18)?;
-        spawn_towers(&mut world, size, 50.0);
-        let spawner = EnemySpawner {
-            enemies_to_spawn: 500,
-        };
-        let s = MainState {
-            world,
-            store,
-            sides,
-            font,
-            spawner,
-        };
-        Ok(s)
+    fn upgrade_summary(&self, tower: Entity) -> Option<String> {
+        let world = &self.sim.world;
+        let kind = world.get_component::<Tower>(tower)?.kind;
+        let upgrades = world.get_component::<Upgrades>(tower)?;
+        let config = &self.sim.config;
+        let tower = &config.towers[kind];
+        let summary = UPGRADE_KEYS
+            .iter()
+            .filter(|(_, upgrade, _)| can_upgrade(tower, *upgrade))
+            .map(|(_, upgrade, label)| {
+                let level = upgrades.level(*upgrade);
+                if level >= config.upgrades.max_level {
+                    format!("{} {} (max)", label, level)
+                } else {
+                    format!("{} {} ({}g)", label, level, config.upgrades.cost(level))
+                }
+            }).collect::<Vec<_>>()
+            .join(", ");
+        Some(format!("{}: {}", tower.name, summary))
     }
 }
 impl event::EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let dt = DeltaTime(timer::duration_to_f64(timer::get_delta(ctx)) as f32);
-        let world = &mut self.world;
-        self.spawner.spawn_enemies(world, &self.sides);
-        move_torwards(world, dt);
-        update_destination(world, &self.sides);
-        move_velocity(world, dt);
-        kill_entities(world);
-        update_orientation(world);
-        animate_explosion(world, dt);
-        shoot_at_enemy(world);
-        kill_enemies(world);
-        StandardMissileSystem::new().hit(world);
-        SpawnMissileSystem::new().hit(world);
+        let frame_time = timer::duration_to_f64(timer::get_delta(ctx)) as f32;
+        for _ in 0..self.timestep.advance(frame_time) {
+            if let Some(playback) = &mut self.playback {
+                if playback.is_finished(&self.sim) {
+                    break;
+                }
+                if let Err(desync) = playback.step(&mut self.sim, &mut self.profiler) {
+                    if self.desync.is_none() {
+                        println!("{}", desync);
+                        self.desync = Some(desync);
+                    }
+                }
+            } else {
+                self.sim.update(self.timestep.tick, &mut self.profiler);
+                if let Some((_, replay)) = &mut self.recording {
+                    replay.record_tick(&self.sim);
+                }
+            }
+        }
         Ok(())
     }
 
@@ -627,18 +229,124 @@ impl event::EventHandler for MainState {
         let fps = timer::get_fps(ctx) as u64;
         graphics::clear(ctx);
         graphics::set_background_color(ctx, graphics::Color::from_rgb(40, 220, 70));
-        draw(&self.store, &mut self.world, ctx)?;
-        draw_explosion(&self.store, ctx, &mut self.world);
-        let count = self.world.matcher::<All<(Read<Enemy>,)>>().count();
+        draw_tiles(&self.store, ctx, &self.sim.map)?;
+        draw_goal(ctx, &self.sim.goal)?;
+        let world = &mut self.sim.world;
+        draw(&self.store, world, ctx, self.timestep.alpha())?;
+        draw_explosion(&self.store, ctx, world);
+        draw_health_bars(&self.store, ctx, world, self.timestep.alpha())?;
+        draw_status_effects(
+            &self.store,
+            ctx,
+            world,
+            self.sim.time,
+            self.timestep.alpha(),
+        )?;
+        if let Some(tower) = self.selected {
+            let pos = world.get_component::<Position>(tower).map(|pos| pos.0);
+            let range = world.get_component::<Targeting>(tower).map(|t| t.range);
+            if let (Some(pos), Some(range)) = (pos, range) {
+                draw_selection(ctx, pos, range)?;
+            }
+        }
+        let count = world.matcher::<All<(Read<Enemy>,)>>().count();
         let text = graphics::Text::new(
             ctx,
-            &format!("FPS: {}, Enemies: {}", fps, count),
+            &format!(
+                "FPS: {}, Wave: {}, Lives: {}, Gold: {}, Enemies: {}",
+                fps, self.sim.spawner.wave, self.sim.lives, self.sim.gold, count
+            ),
             &self.font,
         )?;
         graphics::draw(ctx, &text, na::Point2::new(0.0, 0.0), 0.0)?;
+        let tower = &self.sim.config.towers[self.selected_tower];
+        let text = graphics::Text::new(
+            ctx,
+            &format!("Tower: {} ({} gold)", tower.name, tower.cost),
+            &self.font,
+        )?;
+        graphics::draw(ctx, &text, na::Point2::new(0.0, 24.0), 0.0)?;
+        let replay = self
+            .playback
+            .as_ref()
+            .map(|playback| format!("Replay: tick {} / {}", self.sim.tick, playback.replay.ticks));
+        let lines = replay
+            .into_iter()
+            .chain(self.selected.and_then(|tower| self.upgrade_summary(tower)))
+            .chain(self.error.clone())
+            .chain(self.desync.map(|desync| desync.to_string()));
+        for (idx, line) in lines.enumerate() {
+            let text = graphics::Text::new(ctx, &line, &self.font)?;
+            let dest = na::Point2::new(0.0, 48.0 + 24.0 * idx as f32);
+            graphics::draw(ctx, &text, dest, 0.0)?;
+        }
+        let message = match self.sim.state {
+            GameState::Running if self.is_over() => Some("Replay finished! Press R to restart"),
+            GameState::Running => None,
+            GameState::Victory => Some("Victory! Press R to restart"),
+            GameState::GameOver => Some("Game over! Press R to restart"),
+        };
+        if let Some(message) = message {
+            let text = graphics::Text::new(ctx, message, &self.font)?;
+            let (width, height) = self.sim.level.size();
+            let dest = na::Point2::new(
+                (width - text.width() as f32) / 2.0,
+                (height - text.height() as f32) / 2.0,
+            );
+            graphics::draw(ctx, &text, dest, 0.0)?;
+        }
         graphics::present(ctx);
         Ok(())
     }
+
+    fn mouse_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: event::MouseButton,
+        x: i32,
+        y: i32,
+    ) {
+        if self.sim.state != GameState::Running {
+            return;
+        }
+        let pos = na::Point2::new(x as f32, y as f32);
+        match button {
+            event::MouseButton::Left => self.apply_input(Input::PlaceTower {
+                kind: self.selected_tower,
+                x: pos.x,
+                y: pos.y,
+            }),
+            event::MouseButton::Right => {
+                self.selected = self.sim.tower_at(pos);
+                self.error = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: event::Keycode,
+        _keymod: event::Mod,
+        _repeat: bool,
+    ) {
+        match keycode {
+            event::Keycode::R if self.is_over() => self.restart(),
+            event::Keycode::Escape => ctx.quit().unwrap(),
+            _ if self.is_over() => (),
+            _ => {
+                let upgrade = UPGRADE_KEYS.iter().find(|(key, _, _)| *key == keycode);
+                if let Some(&(_, upgrade, _)) = upgrade {
+                    self.upgrade_selected(upgrade);
+                } else if let Some(kind) = tower_hotkey(keycode) {
+                    if kind < self.sim.config.towers.len() {
+                        self.selected_tower = kind;
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub fn main() {
@@ -646,12 +354,101 @@ pub fn main() {
     let mut c = conf::Conf::new();
     c.window_mode.vsync = false;
 
+    // Left click places a tower, the number keys pick its kind. Right click selects a tower to
+    // upgrade with D (damage), F (fire rate), R (range) and C (cluster fragments). R restarts
+    // once the game is over.
+    // Usage: bench_defense [--config PATH] [--level PATH] [--seed N] [--tick-rate HZ]
+    //                     [--no-crowd] [--headless] [--frames N] [--dt SECONDS]
+    //                     [--record PATH] [--checksum-interval N] [--replay PATH]
+    // The config and level paths are relative to the `resources` directory. Without `--config`
+    // the game uses `/config.ron` if it exists and the built-in defaults otherwise, a config
+    // that was asked for has to exist. Without a level the game is played on the generated open
+    // field. `--no-crowd` turns off the crowd steering to compare timings with and without it.
+    // `--record` writes the last game played to a replay file, with a checksum of the world
+    // every `--checksum-interval` ticks. `--replay` plays one back instead of starting a game,
+    // in a window or with `--headless` as fast as possible, and reports where it desyncs.
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    let seed = arg_value("--seed")
+        .map(|seed| seed.parse().expect("--seed expects an unsigned integer"))
+        .unwrap_or_else(random_seed);
+    let config_path = arg_value("--config").map(String::as_str);
+    let level_path = arg_value("--level").map(String::as_str);
+    let crowd = !args.iter().any(|arg| arg == "--no-crowd");
+    let tick_rate: f32 = arg_value("--tick-rate")
+        .map(|hz| hz.parse().expect("--tick-rate expects ticks per second"))
+        .unwrap_or(60.0);
+    assert!(
+        tick_rate.is_finite() && tick_rate > 0.0,
+        "--tick-rate must be a positive number"
+    );
+    let record_path = arg_value("--record").map(path::PathBuf::from);
+    let checksum_interval = arg_value("--checksum-interval")
+        .map(|n| n.parse().expect("--checksum-interval expects ticks"))
+        .unwrap_or(60);
+    assert!(
+        checksum_interval > 0,
+        "--checksum-interval must be positive"
+    );
+    let replay = arg_value("--replay").map(|replay_path| {
+        Replay::from_file(path::Path::new(replay_path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        })
+    });
+    if args.iter().any(|arg| arg == "--headless") {
+        if let Some(replay) = replay {
+            run_replay(replay);
+            return;
+        }
+        let frames = arg_value("--frames")
+            .map(|f| f.parse().expect("--frames expects an integer"))
+            .unwrap_or(1000);
+        let dt = arg_value("--dt")
+            .map(|dt| dt.parse().expect("--dt expects a number of seconds"))
+            .unwrap_or(1.0 / 60.0);
+        let mut config = Config::load(config_path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        config.crowd.enabled &= crowd;
+        let level = match level_path {
+            Some(level_path) => Level::from_file(&resource_path(level_path), &config),
+            None => {
+                let level = Level::default();
+                level.validate(&config).map(|_| level)
+            }
+        };
+        let level = level.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        run_headless(config, level, frames, DeltaTime(dt), seed);
+        return;
+    }
+
     let ctx = &mut Context::load_from_conf("super_simple", "ggez", c).unwrap();
     if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(manifest_dir);
         path.push("resources");
         ctx.filesystem.mount(&path, true);
     }
-    let state = &mut MainState::new(ctx).unwrap();
+    println!("Seed: {}", seed);
+    let state = &mut MainState::new(ctx, config_path, level_path, seed, tick_rate, crowd)
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+    if let Some(replay) = replay {
+        state.play(replay);
+        apply_window_config(ctx, &state.sim.level).unwrap();
+    } else if let Some(path) = record_path {
+        state.record(path, checksum_interval);
+    }
     event::run(ctx, state).unwrap();
+    state.save_recording();
 }
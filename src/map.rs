@@ -0,0 +1,224 @@
+use crate::{Goal, Sides};
+use nalgebra as na;
+use std::collections::VecDeque;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+pub enum Tile {
+    // Enemies walk here, towers can't be built on it
+    Walkable,
+    // Enemies walk here until a tower is built on it
+    Buildable,
+    // Neither enemies nor towers
+    Blocked,
+}
+
+impl Tile {
+    // How a tile is written in a level: '.' for buildable, '=' for walkable and '#' for blocked
+    pub fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' => Some(Tile::Buildable),
+            '=' => Some(Tile::Walkable),
+            '#' => Some(Tile::Blocked),
+            _ => None,
+        }
+    }
+}
+
+// Column and row of a cell
+pub type Cell = (usize, usize);
+
+// Neighbours of a cell and what it costs to step there, diagonals cost sqrt(2)
+const NEIGHBOURS: [(isize, isize, f32); 8] = [
+    (1, 0, 1.0),
+    (-1, 0, 1.0),
+    (0, 1, 1.0),
+    (0, -1, 1.0),
+    (1, 1, std::f32::consts::SQRT_2),
+    (1, -1, std::f32::consts::SQRT_2),
+    (-1, 1, std::f32::consts::SQRT_2),
+    (-1, -1, std::f32::consts::SQRT_2),
+];
+
+pub struct TileMap {
+    pub cell_size: f32,
+    pub width: usize,
+    pub height: usize,
+    tiles: Vec<Tile>,
+    // Cells covered by a tower
+    occupied: Vec<bool>,
+}
+
+impl TileMap {
+    // `tiles` row by row, or empty to make everything buildable. The waypoints and the goal are
+    // always walkable, enemies need to reach them.
+    pub fn new(
+        (width, height): (f32, f32),
+        cell_size: f32,
+        tiles: &[Tile],
+        sides: &Sides,
+        goal: &Goal,
+    ) -> Self {
+        let columns = (width / cell_size).ceil() as usize;
+        let rows = (height / cell_size).ceil() as usize;
+        let tiles = if tiles.is_empty() {
+            vec![Tile::Buildable; columns * rows]
+        } else {
+            assert_eq!(tiles.len(), columns * rows, "one tile per cell");
+            tiles.to_vec()
+        };
+        let mut map = TileMap {
+            cell_size,
+            width: columns,
+            height: rows,
+            tiles,
+            occupied: vec![false; columns * rows],
+        };
+        for &point in sides.points() {
+            if let Some(cell) = map.cell(point) {
+                map.set_tile(cell, Tile::Walkable);
+            }
+        }
+        for cell in map.footprint(goal.position, goal.radius) {
+            map.set_tile(cell, Tile::Walkable);
+        }
+        map
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn index(&self, (x, y): Cell) -> usize {
+        y * self.width + x
+    }
+
+    // The cell containing `pos`, `None` outside of the map
+    pub fn cell(&self, pos: na::Point2<f32>) -> Option<Cell> {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+        let x = (pos.x / self.cell_size) as usize;
+        let y = (pos.y / self.cell_size) as usize;
+        if x < self.width && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    pub fn center(&self, (x, y): Cell) -> na::Point2<f32> {
+        na::Point2::new(
+            (x as f32 + 0.5) * self.cell_size,
+            (y as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    pub fn tile(&self, cell: Cell) -> Tile {
+        self.tiles[self.index(cell)]
+    }
+
+    pub fn set_tile(&mut self, cell: Cell, tile: Tile) {
+        let idx = self.index(cell);
+        self.tiles[idx] = tile;
+    }
+
+    pub fn is_passable(&self, cell: Cell) -> bool {
+        let idx = self.index(cell);
+        self.tiles[idx] != Tile::Blocked && !self.occupied[idx]
+    }
+
+    // Cells whose centers lie within the square around a circle at `pos`
+    pub fn footprint(&self, pos: na::Point2<f32>, radius: f32) -> Vec<Cell> {
+        let to_cell = |coord: f32| (coord / self.cell_size - 0.5).max(0.0);
+        let min_x = to_cell(pos.x - radius).ceil() as usize;
+        let min_y = to_cell(pos.y - radius).ceil() as usize;
+        let max_x = (to_cell(pos.x + radius).floor() as usize).min(self.width - 1);
+        let max_y = (to_cell(pos.y + radius).floor() as usize).min(self.height - 1);
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .collect()
+    }
+
+    pub fn is_buildable(&self, cells: &[Cell]) -> bool {
+        cells
+            .iter()
+            .all(|&cell| self.tile(cell) == Tile::Buildable && !self.occupied[self.index(cell)])
+    }
+
+    pub fn occupy(&mut self, cells: &[Cell]) {
+        for &cell in cells {
+            let idx = self.index(cell);
+            self.occupied[idx] = true;
+        }
+    }
+
+    // Whether every cell in `targets` can still walk to `from` once `blocked` isn't passable
+    // anymore. A target on an impassable cell only needs a neighbour that can, enemies can walk
+    // out of the cell a tower was built on.
+    pub fn reaches_all(&self, from: Cell, targets: &[Cell], blocked: &[Cell]) -> bool {
+        let passable = |cell: Cell| self.is_passable(cell) && !blocked.contains(&cell);
+        if !passable(from) {
+            return false;
+        }
+        let mut reached = vec![false; self.tiles.len()];
+        let mut queue = VecDeque::new();
+        reached[self.index(from)] = true;
+        queue.push_back(from);
+        while let Some(cell) = queue.pop_front() {
+            for (next, _) in self.steps(cell, &passable) {
+                let idx = self.index(next);
+                if !reached[idx] {
+                    reached[idx] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        targets.iter().all(|&target| {
+            reached[self.index(target)]
+                || self
+                    .adjacent(target)
+                    .any(|(cell, _, _, _)| reached[self.index(cell)])
+        })
+    }
+
+    // Cells around `cell` inside the map, with the offset and the cost of stepping there
+    pub(crate) fn adjacent(
+        &self,
+        (x, y): Cell,
+    ) -> impl Iterator<Item = (Cell, isize, isize, f32)> + '_ {
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy, cost)| {
+            let nx = x as isize + dx;
+            let ny = y as isize + dy;
+            if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+                None
+            } else {
+                Some(((nx as usize, ny as usize), dx, dy, cost))
+            }
+        })
+    }
+
+    // Neighbours of `cell` for which `passable` holds and the cost of stepping there. Diagonal
+    // steps can't cut the corner of an impassable cell.
+    pub(crate) fn steps<'a, F>(
+        &'a self,
+        (x, y): Cell,
+        passable: &'a F,
+    ) -> impl Iterator<Item = (Cell, f32)> + 'a
+    where
+        F: Fn(Cell) -> bool,
+    {
+        let offset = move |dx: isize, dy: isize| {
+            passable(((x as isize + dx) as usize, (y as isize + dy) as usize))
+        };
+        self.adjacent((x, y))
+            .filter_map(move |(cell, dx, dy, cost)| {
+                let diagonal = dx != 0 && dy != 0;
+                let open = passable(cell) && (!diagonal || (offset(dx, 0) && offset(0, dy)));
+                if open {
+                    Some((cell, cost))
+                } else {
+                    None
+                }
+            })
+    }
+}
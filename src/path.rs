@@ -0,0 +1,94 @@
+use crate::{GoalConfig, MoveTorwards, SimRng};
+use nalgebra as na;
+use rand::Rng;
+
+// The lines of waypoints enemies walk between, see `SpawnLine`
+pub struct Sides {
+    waypoints: Vec<Waypoints>,
+}
+impl Sides {
+    pub fn new(lines: Vec<Vec<na::Point2<f32>>>) -> Sides {
+        Sides {
+            waypoints: lines
+                .into_iter()
+                .map(|points| Waypoints { points })
+                .collect(),
+        }
+    }
+
+    pub fn get_random_side(&self, rng: &mut SimRng) -> usize {
+        rng.gen_range(0, self.waypoints.len())
+    }
+    pub fn get_random_point(&self, previous_side: usize, rng: &mut SimRng) -> MoveTorwards {
+        let next_side = (previous_side + 1) % self.waypoints.len();
+        let destination = self.waypoints[next_side].get_random_point(rng);
+        MoveTorwards::new(destination, next_side, 0)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.waypoints.len()
+    }
+
+    // The waypoints of one line
+    pub fn line(&self, side: usize) -> &[na::Point2<f32>] {
+        &self.waypoints[side].points
+    }
+
+    // Every waypoint on every line
+    pub fn points(&self) -> impl Iterator<Item = &na::Point2<f32>> {
+        self.waypoints.iter().flat_map(|line| line.points.iter())
+    }
+
+    // Where an enemy goes after arriving at its current destination
+    pub fn next_destination(
+        &self,
+        previous: &MoveTorwards,
+        goal: &Goal,
+        rng: &mut SimRng,
+    ) -> MoveTorwards {
+        let legs = previous.legs + 1;
+        if legs >= goal.legs {
+            MoveTorwards::new(goal.position, previous.side, legs)
+        } else {
+            MoveTorwards {
+                legs,
+                heading: previous.heading,
+                ..self.get_random_point(previous.side, rng)
+            }
+        }
+    }
+}
+
+pub struct Goal {
+    pub position: na::Point2<f32>,
+    pub radius: f32,
+    pub legs: usize,
+}
+impl Goal {
+    pub fn new(config: &GoalConfig) -> Goal {
+        Goal {
+            position: na::Point2::new(config.x, config.y),
+            radius: config.radius,
+            legs: config.legs,
+        }
+    }
+
+    pub fn overlaps(&self, pos: na::Point2<f32>, radius: f32) -> bool {
+        na::distance(&self.position, &pos) < self.radius + radius
+    }
+
+    pub fn is_heading_here(&self, target: &MoveTorwards) -> bool {
+        target.legs >= self.legs
+    }
+}
+
+pub struct Waypoints {
+    pub points: Vec<na::Point2<f32>>,
+}
+
+impl Waypoints {
+    pub fn get_random_point(&self, rng: &mut SimRng) -> na::Point2<f32> {
+        let index: usize = rng.gen_range(0, self.points.len());
+        self.points[index]
+    }
+}
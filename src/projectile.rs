@@ -0,0 +1,414 @@
+use crate::{
+    AreaDamage, AssetId, Bullet, BulletEntity, Damage, DamageType, Enemy, Explosion, Flip, Missile,
+    Orientation, Position, ProjectileConfig, ProjectileKind, Render, Shoot, SimTime, SpatialGrid,
+    StatusEffects, TimeToLive, Velocity,
+};
+use nalgebra as na;
+use pyro::*;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+pub fn create_radial_missiles<Projectile: Component + Copy>(
+    pos: Position,
+    speed: f32,
+    offset: f32,
+    count: usize,
+    damage: Damage,
+    projectile: Projectile,
+    now: SimTime,
+) -> impl Iterator<Item = Missile<Projectile>> {
+    let step_size = 2.0 * PI / count as f32;
+    (0..count)
+        .scan(0.0, move |acc, _| {
+            *acc += step_size;
+            Some(*acc)
+        }).map(move |angle| {
+            let x = offset * f32::cos(angle);
+            let y = offset * f32::sin(angle);
+            let dir = na::Vector2::new(x, y).normalize();
+            let damage = damage.clone();
+            create_missile(
+                AssetId::SmallMissile,
+                pos,
+                dir,
+                speed,
+                damage,
+                projectile,
+                now,
+            )
+        })
+}
+
+pub trait OnProjectileHit {
+    type Projectile: Component + Sized;
+    // Resisted by the enemies it hits, see `Resistances`
+    const DAMAGE_TYPE: DamageType;
+    fn finish(&mut self, _world: &mut World, _enemy_grid: &SpatialGrid) {}
+    // Called for every enemy a projectile hits, after the hit damage has been queued
+    fn on_projectile_hit(
+        &mut self,
+        pos: Position,
+        enemy: Entity,
+        damage: f32,
+        projectile: &Self::Projectile,
+        now: SimTime,
+    );
+    // `enemy_grid` has to be up to date with the enemy positions, see `update_enemy_grid`
+    fn hit(
+        &mut self,
+        world: &mut World,
+        enemy_grid: &SpatialGrid,
+        config: &ProjectileConfig,
+        now: SimTime,
+    ) {
+        let mut explosions = Vec::new();
+        let mut entities = Vec::new();
+        let mut hits = Vec::new();
+        world
+            .matcher_with_entities::<All<(
+                Read<Self::Projectile>,
+                Read<Position>,
+                Write<Damage>,
+                Write<Velocity>,
+            )>>().for_each(|(entity, (projectile, &missile, damage, vel))| {
+                let colliding_enemy = enemy_grid
+                    .query_radius(missile.0, config.hit_radius)
+                    .find(|(enemy, _)| !damage.hit.contains(enemy));
+                let enemy = match colliding_enemy {
+                    Some((enemy, _)) => enemy,
+                    None => return,
+                };
+                hits.push((enemy, damage.amount, damage.effect));
+                damage.hit.push(enemy);
+                self.on_projectile_hit(missile, enemy, damage.amount, projectile, now);
+                explosions.push((
+                    Explosion {
+                        radius: 0.0,
+                        max_radius: config.explosion_radius,
+                    },
+                    missile,
+                ));
+                if damage.pierce > 0 {
+                    damage.pierce -= 1;
+                    return;
+                }
+                if damage.chain > 0 {
+                    damage.chain -= 1;
+                    let hit = &damage.hit;
+                    let next = enemy_grid
+                        .nearest(missile.0, config.chain_range, |enemy| !hit.contains(&enemy));
+                    if let Some((_, next_pos)) = next {
+                        // Keeps flying the same way if the next enemy is right where it hit
+                        let dir = (next_pos - missile.0).try_normalize(std::f32::EPSILON);
+                        if let Some(dir) = dir {
+                            vel.0 = dir * vel.0.norm();
+                        }
+                        return;
+                    }
+                }
+                entities.push(entity);
+            });
+        for (enemy, damage, effect) in hits {
+            if let Some(enemy) = world.get_component_mut::<Enemy>(enemy) {
+                enemy.take_hit(damage, Self::DAMAGE_TYPE);
+            }
+            if let Some(effect) = effect {
+                if let Some(effects) = world.get_component_mut::<StatusEffects>(enemy) {
+                    effects.apply(effect, now, config.max_burn_stacks);
+                }
+            }
+        }
+        world.append_components(explosions);
+        world.remove_entities(entities);
+        self.finish(world, enemy_grid);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct StandardMissile;
+pub struct StandardMissileSystem;
+impl StandardMissileSystem {
+    pub fn new() -> Self {
+        StandardMissileSystem {}
+    }
+}
+impl OnProjectileHit for StandardMissileSystem {
+    type Projectile = StandardMissile;
+    const DAMAGE_TYPE: DamageType = DamageType::Standard;
+    fn on_projectile_hit(
+        &mut self,
+        _pos: Position,
+        _enemy: Entity,
+        _damage: f32,
+        _projectile: &Self::Projectile,
+        _now: SimTime,
+    ) {
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct HomingMissile {
+    // `None` if there was no enemy left to pick
+    pub target: Option<Entity>,
+    pub turn_rate: f32,
+    pub retarget_range: f32,
+}
+pub struct HomingMissileSystem;
+impl HomingMissileSystem {
+    pub fn new() -> Self {
+        HomingMissileSystem {}
+    }
+}
+impl OnProjectileHit for HomingMissileSystem {
+    type Projectile = HomingMissile;
+    const DAMAGE_TYPE: DamageType = DamageType::Homing;
+    fn on_projectile_hit(
+        &mut self,
+        _pos: Position,
+        _enemy: Entity,
+        _damage: f32,
+        _projectile: &Self::Projectile,
+        _now: SimTime,
+    ) {
+    }
+}
+
+// Spread by a cluster shell when it hits, deals cluster damage like the shell itself
+#[derive(Copy, Clone)]
+pub struct ClusterFragment;
+pub struct ClusterFragmentSystem;
+impl ClusterFragmentSystem {
+    pub fn new() -> Self {
+        ClusterFragmentSystem {}
+    }
+}
+impl OnProjectileHit for ClusterFragmentSystem {
+    type Projectile = ClusterFragment;
+    const DAMAGE_TYPE: DamageType = DamageType::Cluster;
+    fn on_projectile_hit(
+        &mut self,
+        _pos: Position,
+        _enemy: Entity,
+        _damage: f32,
+        _projectile: &Self::Projectile,
+        _now: SimTime,
+    ) {
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SpawnMissile {
+    pub fragments: usize,
+    pub fragment_speed: f32,
+    pub fragment_damage: f32,
+}
+pub struct SpawnMissileSystem {
+    spawn: Vec<Missile<ClusterFragment>>,
+}
+impl SpawnMissileSystem {
+    pub fn new() -> Self {
+        Self { spawn: Vec::new() }
+    }
+}
+impl OnProjectileHit for SpawnMissileSystem {
+    type Projectile = SpawnMissile;
+    const DAMAGE_TYPE: DamageType = DamageType::Cluster;
+    fn on_projectile_hit(
+        &mut self,
+        pos: Position,
+        _enemy: Entity,
+        _damage: f32,
+        projectile: &Self::Projectile,
+        now: SimTime,
+    ) {
+        let missiles = create_radial_missiles(
+            pos,
+            projectile.fragment_speed,
+            15.0,
+            projectile.fragments,
+            Damage::new(projectile.fragment_damage),
+            ClusterFragment {},
+            now,
+        );
+        self.spawn.extend(missiles);
+    }
+    fn finish(&mut self, world: &mut World, _enemy_grid: &SpatialGrid) {
+        let spawn = self.spawn.drain(0..);
+        world.append_components(spawn);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct SplashMissile {
+    pub radius: f32,
+    pub falloff: f32,
+}
+pub struct SplashMissileSystem {
+    explosions: Vec<(Explosion, Position, AreaDamage)>,
+}
+impl SplashMissileSystem {
+    pub fn new() -> Self {
+        SplashMissileSystem {
+            explosions: Vec::new(),
+        }
+    }
+}
+impl OnProjectileHit for SplashMissileSystem {
+    type Projectile = SplashMissile;
+    const DAMAGE_TYPE: DamageType = DamageType::Splash;
+    fn on_projectile_hit(
+        &mut self,
+        pos: Position,
+        enemy: Entity,
+        damage: f32,
+        projectile: &Self::Projectile,
+        _now: SimTime,
+    ) {
+        let explosion = Explosion {
+            radius: 0.0,
+            max_radius: projectile.radius,
+        };
+        // The enemy that was hit directly already took the full damage
+        let area_damage = AreaDamage {
+            damage,
+            falloff: projectile.falloff,
+            hit: vec![enemy],
+        };
+        self.explosions.push((explosion, pos, area_damage));
+    }
+    fn finish(&mut self, world: &mut World, _enemy_grid: &SpatialGrid) {
+        world.append_components(self.explosions.drain(0..));
+    }
+}
+
+// Collects missiles of every projectile kind, so a system can spawn a mix of them in one go
+#[derive(Default)]
+pub struct MissileBatch {
+    standard: Vec<Missile<StandardMissile>>,
+    homing: Vec<Missile<HomingMissile>>,
+    cluster: Vec<Missile<SpawnMissile>>,
+    splash: Vec<Missile<SplashMissile>>,
+}
+impl MissileBatch {
+    // `target` is the enemy the missile was aimed at
+    pub fn push(
+        &mut self,
+        shoot: &Shoot,
+        location: Position,
+        dir: na::Vector2<f32>,
+        target: Entity,
+        now: SimTime,
+    ) {
+        let asset = shoot.missile_asset;
+        let speed = shoot.missile_speed;
+        let damage = Damage {
+            amount: shoot.damage,
+            pierce: shoot.pierce,
+            chain: shoot.chain,
+            effect: shoot.effect,
+            hit: Vec::new(),
+        };
+        match shoot.projectile {
+            ProjectileKind::Standard => {
+                let projectile = StandardMissile {};
+                self.standard.push(create_missile(
+                    asset, location, dir, speed, damage, projectile, now,
+                ))
+            }
+            ProjectileKind::Homing {
+                turn_rate,
+                retarget_range,
+            } => {
+                let projectile = HomingMissile {
+                    target: Some(target),
+                    turn_rate,
+                    retarget_range,
+                };
+                self.homing.push(create_missile(
+                    asset, location, dir, speed, damage, projectile, now,
+                ))
+            }
+            ProjectileKind::Cluster {
+                fragments,
+                fragment_speed,
+                fragment_damage,
+            } => {
+                let projectile = SpawnMissile {
+                    fragments,
+                    fragment_speed,
+                    fragment_damage,
+                };
+                self.cluster.push(create_missile(
+                    asset, location, dir, speed, damage, projectile, now,
+                ))
+            }
+            ProjectileKind::Splash { radius, falloff } => {
+                let projectile = SplashMissile { radius, falloff };
+                self.splash.push(create_missile(
+                    asset, location, dir, speed, damage, projectile, now,
+                ))
+            }
+        }
+    }
+
+    pub fn append(self, world: &mut World) {
+        world.append_components(self.standard);
+        world.append_components(self.homing);
+        world.append_components(self.cluster);
+        world.append_components(self.splash);
+    }
+}
+
+pub fn create_bullet(
+    location: Position,
+    target: Position,
+    speed: f32,
+    now: SimTime,
+) -> BulletEntity {
+    let dir = (target.0 - location.0).normalize() * speed;
+    (
+        location,
+        Velocity(dir),
+        Render {
+            asset: AssetId::Missile,
+            scale: 0.2,
+            inital_rotation: PI / 2.0,
+            previous_position: location.0,
+        },
+        Orientation(0.0),
+        TimeToLive {
+            created: now,
+            time_until_death: Duration::from_secs(3),
+        },
+        Flip::Right,
+        Bullet {},
+    )
+}
+pub fn create_missile<Projectile: Component>(
+    asset: AssetId,
+    location: Position,
+    dir: na::Vector2<f32>,
+    speed: f32,
+    damage: Damage,
+    projectile: Projectile,
+    now: SimTime,
+) -> Missile<Projectile> {
+    (
+        location,
+        Velocity(dir * speed),
+        Render {
+            asset,
+            scale: 1.0,
+            inital_rotation: PI / 2.0,
+            previous_position: location.0,
+        },
+        Orientation(0.0),
+        TimeToLive {
+            created: now,
+            time_until_death: Duration::from_secs(3),
+        },
+        Flip::Right,
+        damage,
+        projectile,
+    )
+}
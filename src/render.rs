@@ -0,0 +1,293 @@
+use crate::{
+    AssetId, Config, Enemy, Explosion, Flip, Goal, Level, Orientation, Position, Render, SimTime,
+    StatusEffects, Tile, TileMap, DEFAULT_CONFIG_PATH,
+};
+use ggez::nalgebra as na;
+use ggez::*;
+use itertools::Itertools;
+use pyro::*;
+use std::f32::consts::PI;
+use std::io::Read as _;
+
+// Loads the config through the ggez filesystem. A config that was asked for has to exist.
+pub fn load_config(ctx: &mut Context, path: Option<&str>) -> GameResult<Config> {
+    let path = match path {
+        Some(path) => path,
+        None if ctx.filesystem.exists(DEFAULT_CONFIG_PATH) => DEFAULT_CONFIG_PATH,
+        None => return Ok(Config::default()),
+    };
+    if !ctx.filesystem.exists(path) {
+        let msg = format!("{}: unable to read config: no such file", path);
+        return Err(GameError::ConfigError(msg));
+    }
+    let mut source = String::new();
+    ctx.filesystem.open(path)?.read_to_string(&mut source)?;
+    Config::parse(&source).map_err(|err| GameError::ConfigError(format!("{}: {}", path, err)))
+}
+
+// Loads a level through the ggez filesystem, unlike the config it has to exist
+pub fn load_level(ctx: &mut Context, path: &str, config: &Config) -> GameResult<Level> {
+    let mut source = String::new();
+    ctx.filesystem.open(path)?.read_to_string(&mut source)?;
+    Level::parse(&source, config)
+        .map_err(|err| GameError::ConfigError(format!("{}: {}", path, err)))
+}
+
+// Sizes the window to fit the level
+pub fn apply_window_config(ctx: &mut Context, level: &Level) -> GameResult<()> {
+    let (width, height) = level.size();
+    graphics::set_resolution(ctx, width as u32, height as u32)?;
+    graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+}
+
+pub struct AssetSettings {
+    pub scale: f32,
+    pub rotation: f32,
+    pub image: graphics::Image,
+}
+
+pub struct AssetStore {
+    assets: Vec<AssetSettings>,
+}
+
+impl AssetStore {
+    pub fn load(ctx: &mut Context) -> GameResult<AssetStore> {
+        let assets = vec![
+            AssetSettings {
+                scale: 1.0,
+                rotation: 0.0,
+                image: graphics::Image::new(ctx, "/grunt.png")?,
+            },
+            AssetSettings {
+                scale: 1.0,
+                rotation: PI / 2.0,
+                image: graphics::Image::new(ctx, "/missile1.png")?,
+            },
+            AssetSettings {
+                scale: 0.7,
+                rotation: PI / 2.0,
+                image: graphics::Image::new(ctx, "/missile2.png")?,
+            },
+            AssetSettings {
+                scale: 1.0,
+                rotation: 0.0,
+                image: graphics::Image::new(ctx, "/tower.png")?,
+            },
+            AssetSettings {
+                scale: 0.01,
+                rotation: 0.0,
+                image: graphics::Image::new(ctx, "/explosion.png")?,
+            },
+            AssetSettings {
+                scale: 1.0,
+                rotation: 0.0,
+                image: graphics::Image::solid(ctx, 1, graphics::WHITE)?,
+            },
+        ];
+        Ok(AssetStore { assets })
+    }
+}
+
+pub fn draw_goal(ctx: &mut Context, goal: &Goal) -> GameResult<()> {
+    graphics::set_color(ctx, graphics::Color::from_rgba(40, 70, 220, 160))?;
+    graphics::circle(
+        ctx,
+        graphics::DrawMode::Fill,
+        graphics::Point2::new(goal.position.x, goal.position.y),
+        goal.radius,
+        0.5,
+    )?;
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+// Tiles towers can't be built on, walkable ones in sand and blocked ones in gray
+pub fn draw_tiles(store: &AssetStore, ctx: &mut Context, map: &TileMap) -> GameResult<()> {
+    let pixel = &store.assets[AssetId::Pixel as usize];
+    let colors = [
+        (Tile::Walkable, graphics::Color::from_rgb(210, 190, 120)),
+        (Tile::Blocked, graphics::Color::from_rgb(90, 90, 90)),
+    ];
+    for (tile, color) in colors.iter() {
+        let mut batch = graphics::spritebatch::SpriteBatch::new(pixel.image.clone());
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if map.tile((x, y)) != *tile {
+                    continue;
+                }
+                let (left, top) = (x as f32 * map.cell_size, y as f32 * map.cell_size);
+                batch.add(graphics::DrawParam {
+                    dest: graphics::Point2::new(left, top),
+                    scale: na::Point2::new(map.cell_size, map.cell_size),
+                    ..Default::default()
+                });
+            }
+        }
+        // Tinted as a whole, like the status effects
+        graphics::set_color(ctx, *color)?;
+        graphics::draw_ex(ctx, &batch, graphics::DrawParam::default())?;
+    }
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+// Outlines the range of the selected tower
+pub fn draw_selection(ctx: &mut Context, pos: na::Point2<f32>, range: f32) -> GameResult<()> {
+    graphics::set_color(ctx, graphics::Color::from_rgba(255, 255, 255, 160))?;
+    graphics::circle(
+        ctx,
+        graphics::DrawMode::Line(2.0),
+        graphics::Point2::new(pos.x, pos.y),
+        range,
+        0.5,
+    )?;
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+pub fn draw_explosion(store: &AssetStore, ctx: &mut Context, world: &mut World) {
+    let circle = &store.assets[AssetId::Explosion as usize];
+    let mut batch = graphics::spritebatch::SpriteBatch::new(circle.image.clone());
+    world
+        .matcher::<All<(Read<Explosion>, Read<Position>)>>()
+        .for_each(|(explosion, pos)| {
+            let alpha = 1.0 - explosion.radius * 255.0 / explosion.max_radius;
+            let param = graphics::DrawParam {
+                dest: graphics::Point2::new(pos.0.x, pos.0.y),
+                rotation: 0.0,
+                offset: na::Point2::new(0.5, 0.5),
+                scale: na::Point2::new(circle.scale, circle.scale) * explosion.radius,
+                // ggez seems to ingore color in batches, but it respects the alpha value
+                color: Some(graphics::Color::from_rgba(255, 0, 0, alpha as u8)),
+                ..Default::default()
+            };
+            batch.add(param);
+        });
+    graphics::draw_ex(ctx, &batch, graphics::DrawParam::default());
+}
+
+// A colored dot above every enemy per active status effect: blue for slowed, orange for burning
+// and yellow for stunned
+pub fn draw_status_effects(
+    store: &AssetStore,
+    ctx: &mut Context,
+    world: &mut World,
+    now: SimTime,
+    alpha: f32,
+) -> GameResult<()> {
+    // Size of a dot in pixels
+    const DOT_SIZE: f32 = 6.0;
+    let indicators: [(fn(&StatusEffects, SimTime) -> bool, f32, graphics::Color); 3] = [
+        (
+            StatusEffects::is_slowed,
+            -DOT_SIZE,
+            graphics::Color::from_rgb(80, 160, 255),
+        ),
+        (
+            StatusEffects::is_burning,
+            0.0,
+            graphics::Color::from_rgb(255, 140, 0),
+        ),
+        (
+            StatusEffects::is_stunned,
+            DOT_SIZE,
+            graphics::Color::from_rgb(255, 255, 0),
+        ),
+    ];
+    let circle = &store.assets[AssetId::Explosion as usize];
+    for (is_active, offset_x, color) in indicators.iter() {
+        let mut batch = graphics::spritebatch::SpriteBatch::new(circle.image.clone());
+        world
+            .matcher::<All<(Read<Position>, Read<Render>, Read<StatusEffects>)>>()
+            .filter(|(_, _, effects)| is_active(effects, now))
+            .for_each(|(pos, render, _)| {
+                let pos = render.previous_position + (pos.0 - render.previous_position) * alpha;
+                let param = graphics::DrawParam {
+                    dest: graphics::Point2::new(pos.x + offset_x, pos.y - 20.0),
+                    offset: na::Point2::new(0.5, 0.5),
+                    scale: na::Point2::new(circle.scale, circle.scale) * DOT_SIZE / 2.0,
+                    ..Default::default()
+                };
+                batch.add(param);
+            });
+        // The batch ignores the color of its sprites, so it is tinted as a whole
+        graphics::set_color(ctx, *color)?;
+        graphics::draw_ex(ctx, &batch, graphics::DrawParam::default())?;
+    }
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+// The remaining health of every enemy as a green bar over a red one, above its sprite
+pub fn draw_health_bars(
+    store: &AssetStore,
+    ctx: &mut Context,
+    world: &mut World,
+    alpha: f32,
+) -> GameResult<()> {
+    // Size of a full bar at a render scale of 1, in pixels
+    const WIDTH: f32 = 24.0;
+    const HEIGHT: f32 = 3.0;
+    // Distance from the center of the enemy to the bar
+    const OFFSET: f32 = 16.0;
+    let pixel = &store.assets[AssetId::Pixel as usize];
+    let mut background = graphics::spritebatch::SpriteBatch::new(pixel.image.clone());
+    let mut foreground = graphics::spritebatch::SpriteBatch::new(pixel.image.clone());
+    world
+        .matcher::<All<(Read<Position>, Read<Render>, Read<Enemy>)>>()
+        .for_each(|(pos, render, enemy)| {
+            let pos = render.previous_position + (pos.0 - render.previous_position) * alpha;
+            let width = WIDTH * render.scale;
+            let dest = graphics::Point2::new(pos.x - width / 2.0, pos.y - OFFSET * render.scale);
+            let health = (enemy.health / enemy.max_health).max(0.0).min(1.0);
+            background.add(graphics::DrawParam {
+                dest,
+                scale: na::Point2::new(width, HEIGHT),
+                ..Default::default()
+            });
+            foreground.add(graphics::DrawParam {
+                dest,
+                scale: na::Point2::new(width * health, HEIGHT),
+                ..Default::default()
+            });
+        });
+    // Like the status effects, each batch is tinted as a whole
+    graphics::set_color(ctx, graphics::Color::from_rgb(200, 30, 30))?;
+    graphics::draw_ex(ctx, &background, graphics::DrawParam::default())?;
+    graphics::set_color(ctx, graphics::Color::from_rgb(40, 200, 40))?;
+    graphics::draw_ex(ctx, &foreground, graphics::DrawParam::default())?;
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+// `alpha` is how far the current frame lies between the previous and the current tick
+pub fn draw(
+    store: &AssetStore,
+    world: &mut World,
+    ctx: &mut Context,
+    alpha: f32,
+) -> GameResult<()> {
+    let submisson = world
+        .matcher::<All<(Read<Position>, Read<Orientation>, Read<Flip>, Read<Render>)>>()
+        .sorted_by(|(_, _, _, left), (_, _, _, right)| Ord::cmp(&left.asset, &right.asset))
+        .into_iter()
+        .group_by(|(_, _, _, render)| render.asset);
+
+    for (key, group) in &submisson {
+        let asset = &store.assets[key as usize];
+        let image = asset.image.clone();
+        let mut batch = graphics::spritebatch::SpriteBatch::new(image);
+        for (pos, orientation, flip, render) in group {
+            let scale_y = match flip {
+                Flip::Left => 1.0,
+                Flip::Right => -1.0,
+            };
+            let pos = render.previous_position + (pos.0 - render.previous_position) * alpha;
+            let param = graphics::DrawParam {
+                dest: graphics::Point2::new(pos.x, pos.y),
+                rotation: orientation.0 + asset.rotation,
+                offset: na::Point2::new(0.5, 0.5),
+                scale: na::Point2::new(render.scale * scale_y, render.scale) * asset.scale,
+                ..Default::default()
+            };
+            batch.add(param);
+        }
+        graphics::draw_ex(ctx, &batch, graphics::DrawParam::default());
+    }
+    Ok(())
+}
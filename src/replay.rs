@@ -0,0 +1,394 @@
+use crate::{
+    report, Config, DeltaTime, Enemy, GameState, Level, PlacementError, Position, Profiler,
+    Simulation, UpgradeError, UpgradeKind,
+};
+use nalgebra as na;
+use pyro::*;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+// Something the player did to the simulation. Towers are referred to by their position, entities
+// aren't stable across runs.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Input {
+    PlaceTower {
+        kind: usize,
+        x: f32,
+        y: f32,
+    },
+    UpgradeTower {
+        x: f32,
+        y: f32,
+        upgrade: UpgradeKind,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputError {
+    Placement(PlacementError),
+    Upgrade(UpgradeError),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Placement(err) => write!(f, "Can't place tower: {}", err),
+            InputError::Upgrade(err) => write!(f, "Can't upgrade: {}", err),
+        }
+    }
+}
+
+impl Input {
+    pub fn apply(&self, sim: &mut Simulation) -> Result<(), InputError> {
+        match *self {
+            Input::PlaceTower { kind, x, y } => sim
+                .place_tower(kind, na::Point2::new(x, y))
+                .map_err(InputError::Placement),
+            Input::UpgradeTower { x, y, upgrade } => {
+                let tower = sim
+                    .tower_at(na::Point2::new(x, y))
+                    .ok_or(InputError::Upgrade(UpgradeError::NoTower))?;
+                sim.upgrade_tower(tower, upgrade)
+                    .map_err(InputError::Upgrade)
+            }
+        }
+    }
+}
+
+// Everything needed to simulate a game again: where it started, what the player did and when,
+// and checksums of the world to notice when the new run drifts away from the recorded one
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Replay {
+    pub seed: u64,
+    // Length of a tick in seconds
+    pub dt: f32,
+    pub config: Config,
+    pub level: Level,
+    // Ticks the recorded game ran for
+    pub ticks: u64,
+    // Each input is applied after its tick, before the next update
+    pub inputs: Vec<(u64, Input)>,
+    pub checksum_interval: u64,
+    // One checksum every `checksum_interval` ticks
+    pub checksums: Vec<(u64, u64)>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(msg) => write!(f, "unable to access replay: {}", msg),
+            ReplayError::Parse(msg) => write!(f, "unable to parse replay: {}", msg),
+            ReplayError::Invalid(msg) => write!(f, "invalid replay: {}", msg),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl Replay {
+    // Starts recording `sim`, which must not have been updated yet
+    pub fn record(sim: &Simulation, dt: DeltaTime, checksum_interval: u64) -> Self {
+        Replay {
+            seed: sim.seed,
+            dt: dt.0,
+            config: sim.config.clone(),
+            level: sim.level.clone(),
+            ticks: 0,
+            inputs: Vec::new(),
+            checksum_interval,
+            checksums: Vec::new(),
+        }
+    }
+
+    pub fn record_input(&mut self, sim: &Simulation, input: Input) {
+        self.inputs.push((sim.tick, input));
+    }
+
+    // Call after every update
+    pub fn record_tick(&mut self, sim: &Simulation) {
+        if sim.tick == self.ticks {
+            return;
+        }
+        self.ticks = sim.tick;
+        if self.ticks % self.checksum_interval == 0 {
+            self.checksums.push((self.ticks, checksum(sim)));
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Replay, ReplayError> {
+        let replay: Replay =
+            ron::de::from_str(source).map_err(|err| ReplayError::Parse(err.to_string()))?;
+        replay.validate()?;
+        Ok(replay)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Replay, ReplayError> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| ReplayError::Io(format!("{}: {}", path.display(), err)))?;
+        Replay::parse(&source)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let source = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(|err| ReplayError::Invalid(err.to_string()))?;
+        fs::write(path, source)
+            .map_err(|err| ReplayError::Io(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn validate(&self) -> Result<(), ReplayError> {
+        let check = |valid: bool, msg: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(ReplayError::Invalid(msg.to_string()))
+            }
+        };
+        self.config
+            .validate()
+            .map_err(|err| ReplayError::Invalid(err.to_string()))?;
+        self.level
+            .validate(&self.config)
+            .map_err(|err| ReplayError::Invalid(err.to_string()))?;
+        check(self.dt > 0.0, "dt must be positive")?;
+        check(
+            self.checksum_interval > 0,
+            "checksum_interval must be at least 1",
+        )?;
+        for (idx, (tick, input)) in self.inputs.iter().enumerate() {
+            let check_input =
+                |valid: bool, msg: &str| check(valid, &format!("inputs[{}]: {}", idx, msg));
+            check_input(*tick <= self.ticks, "can't come after the last tick")?;
+            check_input(
+                idx == 0 || self.inputs[idx - 1].0 <= *tick,
+                "must be in tick order",
+            )?;
+            if let Input::PlaceTower { kind, .. } = input {
+                check_input(
+                    *kind < self.config.towers.len(),
+                    "kind must be one of the towers in the config",
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// FNV-1a over the state that diverges first when two runs drift apart
+pub fn checksum(sim: &Simulation) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |value: u64| {
+        for byte in 0..8 {
+            hash ^= (value >> (byte * 8)) & 0xff;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    add(sim.tick);
+    add(sim.time.0.as_secs());
+    add(u64::from(sim.time.0.subsec_nanos()));
+    add(u64::from(sim.lives));
+    add(u64::from(sim.gold));
+    add(sim.spawner.wave as u64);
+    sim.world
+        .matcher::<All<(Read<Position>,)>>()
+        .for_each(|(pos,)| {
+            add(u64::from(pos.0.x.to_bits()));
+            add(u64::from(pos.0.y.to_bits()));
+        });
+    sim.world
+        .matcher::<All<(Read<Enemy>,)>>()
+        .for_each(|(enemy,)| add(u64::from(enemy.health.to_bits())));
+    hash
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Desync {
+    pub tick: u64,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replay desynced at tick {}: checksum {:016x}, recorded {:016x}",
+            self.tick, self.actual, self.expected
+        )
+    }
+}
+
+// Feeds the recorded inputs to a new simulation and compares the checksums along the way
+pub struct Playback {
+    pub replay: Replay,
+    next_input: usize,
+    next_checksum: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback {
+            replay,
+            next_input: 0,
+            next_checksum: 0,
+        }
+    }
+
+    // The simulation to play the replay on, as it was when the recording started
+    pub fn simulation(&self) -> Simulation {
+        let replay = &self.replay;
+        Simulation::new(replay.config.clone(), replay.level.clone(), replay.seed)
+    }
+
+    pub fn dt(&self) -> DeltaTime {
+        DeltaTime(self.replay.dt)
+    }
+
+    pub fn is_finished(&self, sim: &Simulation) -> bool {
+        sim.tick >= self.replay.ticks || sim.state != GameState::Running
+    }
+
+    // Applies the inputs due and updates once. A desync is reported once, playback goes on.
+    pub fn step(&mut self, sim: &mut Simulation, profiler: &mut Profiler) -> Result<(), Desync> {
+        while let Some(&(tick, input)) = self.replay.inputs.get(self.next_input) {
+            if tick > sim.tick {
+                break;
+            }
+            // Only inputs that worked are recorded, these fail once the replay desynced
+            let _ = input.apply(sim);
+            self.next_input += 1;
+        }
+        sim.update(self.dt(), profiler);
+        match self.replay.checksums.get(self.next_checksum) {
+            Some(&(tick, expected)) if tick == sim.tick => {
+                self.next_checksum += 1;
+                let actual = checksum(sim);
+                if actual == expected {
+                    Ok(())
+                } else {
+                    Err(Desync {
+                        tick,
+                        expected,
+                        actual,
+                    })
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Plays a replay back without a window, as fast as possible, and reports like `run_headless`
+pub fn run_replay(replay: Replay) {
+    println!("Seed: {}, Ticks: {}", replay.seed, replay.ticks);
+    let mut playback = Playback::new(replay);
+    let mut sim = playback.simulation();
+    let mut profiler = Profiler::new();
+    let start = Instant::now();
+    let mut frames_run = 0;
+    let mut desyncs = 0;
+    while !playback.is_finished(&sim) {
+        if let Err(desync) = playback.step(&mut sim, &mut profiler) {
+            if desyncs == 0 {
+                println!("{}", desync);
+            }
+            desyncs += 1;
+        }
+        frames_run += 1;
+    }
+    report(&sim, &profiler, frames_run, playback.dt(), start.elapsed());
+    let checked = playback.next_checksum;
+    println!(
+        "Checksums: {} matched, {} desynced",
+        checked - desyncs,
+        desyncs
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: DeltaTime = DeltaTime(1.0 / 60.0);
+    const TICKS: usize = 600;
+
+    fn simulation(seed: u64) -> Simulation {
+        Simulation::new(Config::default(), Level::default(), seed)
+    }
+
+    // A game with a tower placed before the first wave spawns
+    fn record(seed: u64) -> Replay {
+        let mut sim = simulation(seed);
+        let mut replay = Replay::record(&sim, DT, 30);
+        let mut profiler = Profiler::new();
+        for tick in 0..TICKS {
+            if tick == 10 {
+                let input = Input::PlaceTower {
+                    kind: 0,
+                    x: 250.0,
+                    y: 200.0,
+                };
+                input.apply(&mut sim).unwrap();
+                replay.record_input(&sim, input);
+            }
+            sim.update(DT, &mut profiler);
+            replay.record_tick(&sim);
+        }
+        replay
+    }
+
+    // Plays the whole replay, returning the first desync
+    fn play(replay: Replay) -> Result<Simulation, Desync> {
+        let mut playback = Playback::new(replay);
+        let mut sim = playback.simulation();
+        let mut profiler = Profiler::new();
+        while !playback.is_finished(&sim) {
+            playback.step(&mut sim, &mut profiler)?;
+        }
+        Ok(sim)
+    }
+
+    #[test]
+    fn checksum_follows_the_seed() {
+        let mut profiler = Profiler::new();
+        let mut sims = [simulation(7), simulation(7), simulation(8)];
+        for sim in &mut sims {
+            for _ in 0..TICKS {
+                sim.update(DT, &mut profiler);
+            }
+        }
+        assert_eq!(checksum(&sims[0]), checksum(&sims[1]));
+        assert_ne!(checksum(&sims[0]), checksum(&sims[2]));
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let replay = record(7);
+        assert_eq!(replay.ticks, TICKS as u64);
+        assert_eq!(replay.checksums.len(), TICKS / 30);
+        let source = ron::ser::to_string_pretty(&replay, Default::default()).unwrap();
+        let replay = Replay::parse(&source).unwrap();
+        let (_, last) = *replay.checksums.last().unwrap();
+        let sim = play(replay).unwrap();
+        assert_eq!(sim.tick, TICKS as u64);
+        assert_eq!(checksum(&sim), last);
+    }
+
+    #[test]
+    fn playback_detects_desync() {
+        let mut replay = record(7);
+        replay.inputs.clear();
+        let desync = play(replay).err().expect("missing tower should desync");
+        assert_eq!(desync.tick, 30);
+    }
+}
@@ -0,0 +1,67 @@
+use rand::prng::XorShiftRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+// Pinned to a concrete algorithm instead of rand's `StdRng`, which may change between releases.
+// Helpers like `gen_range` and `shuffle` still may, so runs only repeat on the same rand version.
+pub type SimRng = XorShiftRng;
+
+pub fn random_seed() -> u64 {
+    thread_rng().gen()
+}
+
+pub fn seeded_rng(seed: u64) -> SimRng {
+    // Expand the seed with splitmix64, XorShift misbehaves with low entropy seeds like 0
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        let value = next();
+        for (idx, byte) in chunk.iter_mut().enumerate() {
+            *byte = (value >> (idx * 8)) as u8;
+        }
+    }
+    SimRng::from_seed(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(seed: u64) -> Vec<u64> {
+        let mut rng = seeded_rng(seed);
+        (0..3).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn seed_repeats_numbers() {
+        assert_eq!(numbers(42), numbers(42));
+        assert_ne!(numbers(0), numbers(1));
+    }
+
+    // Fails if the algorithm or the seed expansion changes, which breaks every recorded seed
+    #[test]
+    fn seed_gives_known_numbers() {
+        assert_eq!(
+            numbers(0),
+            [
+                2025292266441889471,
+                15908494403214710823,
+                821458454966161110
+            ]
+        );
+        assert_eq!(
+            numbers(42),
+            [
+                6361036340049788797,
+                9983471945039871035,
+                11287486512541389741
+            ]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    animate_explosion, burn_damage, check_tower_placement, explosion_damage, follow_flow_fields,
+    kill_enemies, kill_entities, move_velocity, reach_goal, seeded_rng, shoot_at_enemy,
+    spawn_tower, spawn_towers, steer_crowd, steer_homing, store_previous_positions,
+    update_destination, update_enemy_grid, update_orientation, upgrade_tower,
+    ClusterFragmentSystem, Config, Damage, DeltaTime, Enemy, Explosion, FlowFields, Goal,
+    HomingMissileSystem, Level, OnProjectileHit, Paths, PlacementError, Position, Shoot, Sides,
+    SimRng, SimTime, SpatialGrid, SpawnMissileSystem, SplashMissileSystem, StandardMissileSystem,
+    TileMap, Tower, UpgradeError, UpgradeKind, WaveSpawner,
+};
+use nalgebra as na;
+use pyro::*;
+use std::time::{Duration, Instant};
+
+pub fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+pub struct Profiler {
+    pub timings: Vec<(&'static str, Duration)>,
+}
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            timings: Vec::new(),
+        }
+    }
+    pub fn time<R, F: FnOnce() -> R>(&mut self, name: &'static str, f: F) -> R {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        if let Some((_, total)) = self.timings.iter_mut().find(|(n, _)| *n == name) {
+            *total += elapsed;
+        } else {
+            self.timings.push((name, elapsed));
+        }
+        result
+    }
+}
+
+// Turns variable frame times into a whole number of fixed simulation ticks. Frames that fall
+// behind by more than `max_ticks_per_frame` drop the excess instead of spiraling further behind.
+pub struct FixedTimestep {
+    pub tick: DeltaTime,
+    pub max_ticks_per_frame: usize,
+    accumulator: f32,
+}
+impl FixedTimestep {
+    pub fn new(tick_rate: f32, max_ticks_per_frame: usize) -> Self {
+        FixedTimestep {
+            tick: DeltaTime(1.0 / tick_rate),
+            max_ticks_per_frame,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn advance(&mut self, frame_time: f32) -> usize {
+        self.accumulator += frame_time;
+        let ticks = (self.accumulator / self.tick.0) as usize;
+        if ticks > self.max_ticks_per_frame {
+            self.accumulator = 0.0;
+            self.max_ticks_per_frame
+        } else {
+            self.accumulator -= ticks as f32 * self.tick.0;
+            ticks
+        }
+    }
+
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick.0).min(1.0)
+    }
+}
+
+pub const ENEMY_GRID_CELL_SIZE: f32 = 32.0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GameState {
+    Running,
+    Victory,
+    GameOver,
+}
+
+pub struct Simulation {
+    pub config: Config,
+    pub level: Level,
+    pub world: World,
+    pub sides: Sides,
+    pub goal: Goal,
+    pub lives: u32,
+    pub gold: u32,
+    pub state: GameState,
+    pub spawner: WaveSpawner,
+    pub seed: u64,
+    pub rng: SimRng,
+    pub time: SimTime,
+    // Updates simulated while the game was running, replays refer to moments by this
+    pub tick: u64,
+    pub enemy_grid: SpatialGrid,
+    pub map: TileMap,
+    pub flow: FlowFields,
+}
+impl Simulation {
+    // `level` has to be validated against `config`
+    pub fn new(config: Config, level: Level, seed: u64) -> Self {
+        let mut world = World::new();
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let lives = config.lives;
+        let gold = config.starting_gold;
+        let mut map = level.map(&sides, &goal);
+        spawn_towers(&mut world, &config, &level);
+        let radius = config.placement.radius;
+        world
+            .matcher::<All<(Read<Position>, Read<Tower>)>>()
+            .for_each(|(pos, _)| {
+                let cells = map.footprint(pos.0, radius);
+                map.occupy(&cells);
+            });
+        let flow = FlowFields::new(&map, &sides, &goal);
+        let spawner = WaveSpawner::new(
+            level.waves.clone(),
+            config.wave_growth,
+            config.enemies.clone(),
+        );
+        Simulation {
+            config,
+            level,
+            world,
+            sides,
+            goal,
+            lives,
+            gold,
+            state: GameState::Running,
+            spawner,
+            seed,
+            rng: seeded_rng(seed),
+            time: SimTime::default(),
+            tick: 0,
+            enemy_grid: SpatialGrid::new(ENEMY_GRID_CELL_SIZE),
+            map,
+            flow,
+        }
+    }
+
+    // Places a tower of the given kind for the player, paid with gold
+    pub fn place_tower(&mut self, kind: usize, pos: na::Point2<f32>) -> Result<(), PlacementError> {
+        let tower = &self.config.towers[kind];
+        if self.gold < tower.cost {
+            return Err(PlacementError::NotEnoughGold);
+        }
+        let size = self.level.size();
+        let radius = self.config.placement.radius;
+        let cells = check_tower_placement(
+            &self.world,
+            &self.map,
+            size,
+            &self.sides,
+            &self.goal,
+            pos,
+            radius,
+        )?;
+        self.gold -= tower.cost;
+        spawn_tower(&mut self.world, pos, kind, tower);
+        self.map.occupy(&cells);
+        self.flow.block(&self.map, &cells);
+        Ok(())
+    }
+
+    pub fn upgrade_tower(
+        &mut self,
+        tower: Entity,
+        upgrade: UpgradeKind,
+    ) -> Result<(), UpgradeError> {
+        upgrade_tower(
+            &mut self.world,
+            tower,
+            upgrade,
+            &mut self.gold,
+            &self.config,
+        )
+    }
+
+    // The tower whose footprint contains `pos`
+    pub fn tower_at(&self, pos: na::Point2<f32>) -> Option<Entity> {
+        let radius = self.config.placement.radius;
+        self.world
+            .matcher_with_entities::<All<(Read<Position>, Read<Tower>)>>()
+            .find(|(_, (tower_pos, _))| na::distance(&tower_pos.0, &pos) <= radius)
+            .map(|(entity, _)| entity)
+    }
+
+    // Does nothing once the game has been won or lost
+    pub fn update(&mut self, dt: DeltaTime, profiler: &mut Profiler) {
+        if self.state != GameState::Running {
+            return;
+        }
+        self.time.advance(dt);
+        self.tick += 1;
+        let now = self.time;
+        let world = &mut self.world;
+        let sides = &self.sides;
+        let goal = &self.goal;
+        let spawner = &mut self.spawner;
+        let rng = &mut self.rng;
+        let enemy_grid = &mut self.enemy_grid;
+        let map = &self.map;
+        let flow = &self.flow;
+        let projectile_config = &self.config.projectile;
+        let crowd_config = &self.config.crowd;
+        profiler.time("store_previous_positions", || {
+            store_previous_positions(world)
+        });
+        profiler.time("spawn_enemies", || {
+            spawner.spawn_enemies(world, sides, goal, rng, now)
+        });
+        if crowd_config.enabled {
+            profiler.time("steer_crowd", || {
+                steer_crowd(world, enemy_grid, crowd_config)
+            });
+        }
+        profiler.time("follow_flow_fields", || {
+            follow_flow_fields(world, flow, map, goal, dt, now)
+        });
+        let reached = profiler.time("reach_goal", || reach_goal(world, goal));
+        profiler.time("update_destination", || {
+            update_destination(world, sides, goal, rng)
+        });
+        profiler.time("move_velocity", || move_velocity(world, dt));
+        profiler.time("kill_entities", || kill_entities(world, now));
+        profiler.time("update_orientation", || update_orientation(world));
+        profiler.time("animate_explosion", || animate_explosion(world, dt));
+        profiler.time("burn_damage", || burn_damage(world, dt, now));
+        let gold = profiler.time("kill_enemies", || kill_enemies(world));
+        profiler.time("update_enemy_grid", || update_enemy_grid(world, enemy_grid));
+        profiler.time("steer_homing", || steer_homing(world, dt, enemy_grid));
+        profiler.time("shoot_at_enemy", || {
+            let paths = Paths { flow, map, goal };
+            shoot_at_enemy(world, now, enemy_grid, paths, rng)
+        });
+        profiler.time("standard_missile_hit", || {
+            StandardMissileSystem::new().hit(world, enemy_grid, projectile_config, now)
+        });
+        profiler.time("homing_missile_hit", || {
+            HomingMissileSystem::new().hit(world, enemy_grid, projectile_config, now)
+        });
+        profiler.time("spawn_missile_hit", || {
+            SpawnMissileSystem::new().hit(world, enemy_grid, projectile_config, now)
+        });
+        profiler.time("cluster_fragment_hit", || {
+            ClusterFragmentSystem::new().hit(world, enemy_grid, projectile_config, now)
+        });
+        profiler.time("splash_missile_hit", || {
+            SplashMissileSystem::new().hit(world, enemy_grid, projectile_config, now)
+        });
+        profiler.time("explosion_damage", || explosion_damage(world, enemy_grid));
+
+        self.gold += gold;
+        self.lives = self.lives.saturating_sub(reached as u32);
+        let waves_to_win = self.config.waves_to_win;
+        if self.lives == 0 {
+            self.state = GameState::GameOver;
+        } else if waves_to_win > 0 && self.spawner.cleared() >= waves_to_win {
+            self.state = GameState::Victory;
+        }
+    }
+}
+
+// Steps the simulation without a window, so it can be benchmarked on machines without a display
+pub fn run_headless(config: Config, level: Level, frames: usize, dt: DeltaTime, seed: u64) {
+    println!("Seed: {}", seed);
+    let mut sim = Simulation::new(config, level, seed);
+    let mut profiler = Profiler::new();
+    let start = Instant::now();
+    let mut frames_run = 0;
+    while frames_run < frames && sim.state == GameState::Running {
+        sim.update(dt, &mut profiler);
+        frames_run += 1;
+    }
+    report(&sim, &profiler, frames_run, dt, start.elapsed());
+}
+
+// Timings per system and the state the simulation ended in
+pub(crate) fn report(
+    sim: &Simulation,
+    profiler: &Profiler,
+    frames_run: usize,
+    dt: DeltaTime,
+    elapsed: Duration,
+) {
+    let elapsed = duration_to_secs(elapsed);
+    println!(
+        "Simulated {} frames (dt = {:.4}s) in {:.3}s",
+        frames_run, dt.0, elapsed
+    );
+    println!(
+        "{:<24}{:>12}{:>16}",
+        "system", "total (ms)", "per frame (us)"
+    );
+    for (name, total) in &profiler.timings {
+        let total = duration_to_secs(*total);
+        println!(
+            "{:<24}{:>12.3}{:>16.3}",
+            name,
+            total * 1000.0,
+            total * 1_000_000.0 / frames_run.max(1) as f64
+        );
+    }
+    println!(
+        "State: {:?}, Lives: {}, Wave: {}",
+        sim.state, sim.lives, sim.spawner.wave
+    );
+    let world = &sim.world;
+    println!(
+        "Entities: {}, Enemies: {}, Towers: {}, Missiles: {}, Explosions: {}",
+        world.entities().count(),
+        world.matcher::<All<(Read<Enemy>,)>>().count(),
+        world.matcher::<All<(Read<Shoot>,)>>().count(),
+        world.matcher::<All<(Read<Damage>,)>>().count(),
+        world.matcher::<All<(Read<Explosion>,)>>().count(),
+    );
+}
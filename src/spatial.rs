@@ -0,0 +1,83 @@
+use crate::{Enemy, Position};
+use nalgebra as na;
+use pyro::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Uniform grid over the plane, hashed so that entities outside of the window still land in a
+// cell. Buckets are cleared instead of dropped on rebuild to reuse their allocations.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(Entity, na::Point2<f32>)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, pos: na::Point2<f32>) -> (i32, i32) {
+        (
+            (pos.x / self.cell_size).floor() as i32,
+            (pos.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        for bucket in self.cells.values_mut() {
+            bucket.clear();
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, pos: na::Point2<f32>) {
+        let cell = self.cell(pos);
+        self.cells
+            .entry(cell)
+            .or_insert_with(Vec::new)
+            .push((entity, pos));
+    }
+
+    // Every entry within `radius` of `pos`, in no particular order
+    pub fn query_radius<'a>(
+        &'a self,
+        pos: na::Point2<f32>,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, na::Point2<f32>)> + 'a {
+        let (min_x, min_y) = self.cell(pos - na::Vector2::new(radius, radius));
+        let (max_x, max_y) = self.cell(pos + na::Vector2::new(radius, radius));
+        (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flat_map(|bucket| bucket.iter().cloned())
+            .filter(move |(_, other)| na::distance(&pos, other) <= radius)
+    }
+
+    // The closest entry within `radius` of `pos` that passes `filter`
+    pub fn nearest<F>(
+        &self,
+        pos: na::Point2<f32>,
+        radius: f32,
+        filter: F,
+    ) -> Option<(Entity, na::Point2<f32>)>
+    where
+        F: Fn(Entity) -> bool,
+    {
+        self.query_radius(pos, radius)
+            .filter(|&(entity, _)| filter(entity))
+            .min_by(|(_, left), (_, right)| {
+                na::distance(&pos, left)
+                    .partial_cmp(&na::distance(&pos, right))
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
+
+pub fn update_enemy_grid(world: &World, grid: &mut SpatialGrid) {
+    grid.clear();
+    world
+        .matcher_with_entities::<All<(Read<Enemy>, Read<Position>)>>()
+        .for_each(|(entity, (_, pos))| grid.insert(entity, pos.0));
+}
@@ -0,0 +1,171 @@
+use crate::{
+    AssetId, Cell, Config, Enemy, Flip, Goal, Level, MoveTorwards, Orientation, Position, Recover,
+    Render, Resistances, Shoot, Sides, SimRng, Speed, StatusEffects, Targeting, TileMap, Tower,
+    TowerConfig, Upgrades,
+};
+use nalgebra as na;
+use pyro::*;
+use rand::Rng;
+use std::fmt;
+
+// Stats of a single spawn batch, after the wave modifiers have been applied
+#[derive(Copy, Clone, Debug)]
+pub struct EnemyStats {
+    pub asset: AssetId,
+    pub scale: f32,
+    pub health: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub gold: u32,
+    pub armor: f32,
+    pub resistances: Resistances,
+    pub flying: bool,
+}
+
+// Spawns `count` enemies on the waypoint lines. Flying enemies head straight for the goal, the
+// others walk between the lines first.
+pub fn spawn_random_enemies(
+    world: &mut World,
+    count: usize,
+    sides: &Sides,
+    goal: &Goal,
+    stats: &EnemyStats,
+    rng: &mut SimRng,
+) {
+    let ships = (0..count).map(|_| {
+        let side = sides.get_random_side(rng);
+        let move_torwards = sides.get_random_point(side, rng);
+        let pos = sides.get_random_point(move_torwards.side, rng).destination;
+        let move_torwards = if stats.flying {
+            MoveTorwards::new(goal.position, move_torwards.side, goal.legs)
+        } else {
+            move_torwards
+        };
+        (
+            Position(pos),
+            move_torwards,
+            Orientation(0.0),
+            Speed(rng.gen_range(stats.min_speed, stats.max_speed)),
+            Render {
+                asset: stats.asset,
+                scale: stats.scale,
+                inital_rotation: 0.0,
+                previous_position: pos,
+            },
+            Flip::Right,
+            Enemy {
+                health: stats.health,
+                max_health: stats.health,
+                gold: stats.gold,
+                armor: stats.armor,
+                resistances: stats.resistances,
+                flying: stats.flying,
+            },
+            StatusEffects::none(),
+        )
+    });
+    world.append_components(ships);
+}
+
+// `kind` is the index of `tower` in `Config::towers`
+pub fn spawn_tower(world: &mut World, pos: na::Point2<f32>, kind: usize, tower: &TowerConfig) {
+    world.append_components(Some((
+        Position(pos),
+        Render {
+            asset: tower.asset,
+            scale: tower.scale,
+            inital_rotation: 0.0,
+            previous_position: pos,
+        },
+        Shoot {
+            recover: Recover::new(tower.recover()),
+            missile_asset: tower.missile_asset,
+            missile_speed: tower.missile_speed,
+            damage: tower.damage,
+            pierce: tower.pierce,
+            chain: tower.chain,
+            effect: tower.effect,
+            projectile: tower.projectile,
+        },
+        Targeting {
+            strategy: tower.targeting,
+            range: tower.range,
+            volley: tower.volley,
+        },
+        Orientation(0.0),
+        Flip::Right,
+        Tower { kind },
+        Upgrades::default(),
+    )));
+}
+
+// The towers the level starts with
+pub fn spawn_towers(world: &mut World, config: &Config, level: &Level) {
+    for tower in &level.towers {
+        let kind = config
+            .tower_kind(&tower.tower)
+            .expect("level towers are validated against the config");
+        let pos = na::Point2::new(tower.x, tower.y);
+        spawn_tower(world, pos, kind, &config.towers[kind]);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PlacementError {
+    NotEnoughGold,
+    OutOfBounds,
+    OverlapsTower,
+    // On a tile that isn't buildable, like the waypoints or the goal
+    NotBuildable,
+    // Would cut the goal off from the waypoints or from an enemy
+    BlocksPath,
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            PlacementError::NotEnoughGold => "not enough gold",
+            PlacementError::OutOfBounds => "towers have to be placed inside the level",
+            PlacementError::OverlapsTower => "too close to another tower",
+            PlacementError::NotBuildable => "towers can't be built here",
+            PlacementError::BlocksPath => "the enemies need a way to the goal",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+// Returns the cells the tower would cover, see `TileMap::occupy`
+pub fn check_tower_placement(
+    world: &World,
+    map: &TileMap,
+    (width, height): (f32, f32),
+    sides: &Sides,
+    goal: &Goal,
+    pos: na::Point2<f32>,
+    radius: f32,
+) -> Result<Vec<Cell>, PlacementError> {
+    if pos.x < radius || pos.y < radius || pos.x > width - radius || pos.y > height - radius {
+        return Err(PlacementError::OutOfBounds);
+    }
+    let overlaps_tower = world
+        .matcher::<All<(Read<Position>, Read<Shoot>)>>()
+        .any(|(tower_pos, _)| na::distance(&tower_pos.0, &pos) < 2.0 * radius);
+    if overlaps_tower {
+        return Err(PlacementError::OverlapsTower);
+    }
+    let cells = map.footprint(pos, radius);
+    if goal.overlaps(pos, radius) || !map.is_buildable(&cells) {
+        return Err(PlacementError::NotBuildable);
+    }
+    let goal_cell = map.cell(goal.position).ok_or(PlacementError::BlocksPath)?;
+    let waypoints = sides.points().filter_map(|&point| map.cell(point));
+    let enemies = world
+        .matcher::<All<(Read<Position>, Read<Enemy>)>>()
+        .filter(|(_, enemy)| !enemy.flying)
+        .filter_map(|(pos, _)| map.cell(pos.0));
+    let targets: Vec<_> = waypoints.chain(enemies).collect();
+    if !map.reaches_all(goal_cell, &targets, &cells) {
+        return Err(PlacementError::BlocksPath);
+    }
+    Ok(cells)
+}
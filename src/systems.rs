@@ -0,0 +1,444 @@
+use crate::{
+    AreaDamage, CrowdConfig, Damage, DamageType, DeltaTime, Enemy, Explosion, Flip, FlowFields,
+    Goal, HomingMissile, MissileBatch, MoveTorwards, Orientation, Paths, Position, Render, Shoot,
+    Sides, SimRng, SimTime, SpatialGrid, Speed, StatusEffects, TargetStrategy, Targeting, TileMap,
+    TimeToLive, Velocity,
+};
+use nalgebra as na;
+use pyro::*;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+
+pub fn select_targets(
+    world: &World,
+    enemy_grid: &SpatialGrid,
+    paths: Paths,
+    pos: Position,
+    targeting: &Targeting,
+    rng: &mut SimRng,
+) -> Vec<(Entity, na::Point2<f32>)> {
+    let mut candidates: Vec<_> = enemy_grid.query_radius(pos.0, targeting.range).collect();
+    if targeting.strategy == TargetStrategy::Random {
+        rng.shuffle(&mut candidates);
+        candidates.truncate(targeting.volley);
+        return candidates;
+    }
+    // Lowest first, compared by the first value and then by the second
+    let key = |entity: Entity, enemy_pos: na::Point2<f32>| match targeting.strategy {
+        TargetStrategy::Nearest => (na::distance(&pos.0, &enemy_pos), 0.0),
+        TargetStrategy::FarthestAlongPath => {
+            let target = world.get_component::<MoveTorwards>(entity);
+            let enemy = world.get_component::<Enemy>(entity);
+            match (target, enemy) {
+                (Some(target), Some(enemy)) => {
+                    let (legs, distance) = paths.remaining(target, enemy_pos, enemy.flying);
+                    (legs as f32, distance)
+                }
+                _ => (f32::MAX, 0.0),
+            }
+        }
+        TargetStrategy::LowestHealth => world
+            .get_component::<Enemy>(entity)
+            .map_or((f32::MAX, 0.0), |enemy| (enemy.health, 0.0)),
+        TargetStrategy::HighestHealth => world
+            .get_component::<Enemy>(entity)
+            .map_or((f32::MAX, 0.0), |enemy| (-enemy.health, 0.0)),
+        TargetStrategy::Random => (0.0, 0.0),
+    };
+    let mut keyed: Vec<_> = candidates
+        .into_iter()
+        .map(|(entity, enemy_pos)| (key(entity, enemy_pos), entity, enemy_pos))
+        .collect();
+    keyed.sort_by(|(left, _, _), (right, _, _)| left.partial_cmp(right).unwrap_or(Ordering::Equal));
+    keyed
+        .into_iter()
+        .take(targeting.volley)
+        .map(|(_, entity, enemy_pos)| (entity, enemy_pos))
+        .collect()
+}
+
+pub fn shoot_at_enemy(
+    world: &mut World,
+    now: SimTime,
+    enemy_grid: &SpatialGrid,
+    paths: Paths,
+    rng: &mut SimRng,
+) {
+    let mut projectiles = MissileBatch::default();
+    world
+        .matcher::<All<(Read<Position>, Write<Shoot>, Read<Targeting>)>>()
+        .for_each(|(&spawn_pos, shoot, targeting)| {
+            if !shoot.recover.is_ready(now) {
+                return;
+            }
+            let targets = select_targets(world, enemy_grid, paths, spawn_pos, targeting, rng);
+            if targets.is_empty() {
+                return;
+            }
+            shoot.recover.action(now);
+            for (target, target_pos) in targets {
+                let dir = (target_pos - spawn_pos.0).normalize();
+                let offset = dir * 30.0;
+                let new_pos = Position(spawn_pos.0 + offset);
+                projectiles.push(shoot, new_pos, dir, target, now);
+            }
+        });
+    projectiles.append(world);
+}
+
+// Turns homing missiles towards their target, `enemy_grid` is used to find a new one once the
+// target is dead or has already been hit by the missile
+pub fn steer_homing(world: &mut World, dt: DeltaTime, enemy_grid: &SpatialGrid) {
+    world
+        .matcher::<All<(
+            Write<HomingMissile>,
+            Read<Position>,
+            Write<Velocity>,
+            Read<Damage>,
+        )>>().for_each(|(homing, pos, vel, damage)| {
+            let is_valid = |target: Entity| {
+                !damage.hit.contains(&target)
+                    && world
+                        .get_component::<Enemy>(target)
+                        .map(|enemy| enemy.health > 0.0)
+                        .unwrap_or(false)
+            };
+            if !homing.target.map(is_valid).unwrap_or(false) {
+                homing.target = enemy_grid
+                    .nearest(pos.0, homing.retarget_range, is_valid)
+                    .map(|(enemy, _)| enemy);
+            }
+            let target_pos = homing
+                .target
+                .and_then(|target| world.get_component::<Position>(target));
+            let target_pos = match target_pos {
+                Some(target_pos) => target_pos.0,
+                None => return,
+            };
+            let speed = vel.0.norm();
+            let current = vel.0.y.atan2(vel.0.x);
+            let to_target = target_pos - pos.0;
+            let desired = to_target.y.atan2(to_target.x);
+            let mut turn = desired - current;
+            if turn > PI {
+                turn -= 2.0 * PI;
+            } else if turn < -PI {
+                turn += 2.0 * PI;
+            }
+            let max_turn = homing.turn_rate * dt.0;
+            let angle = current + turn.max(-max_turn).min(max_turn);
+            vel.0 = na::Vector2::new(angle.cos(), angle.sin()) * speed;
+        });
+}
+
+pub fn store_previous_positions(world: &mut World) {
+    world
+        .matcher::<All<(Read<Position>, Write<Render>)>>()
+        .for_each(|(pos, render)| {
+            render.previous_position = pos.0;
+        });
+}
+
+pub fn update_orientation(world: &mut World) {
+    world
+        .matcher::<All<(Read<Velocity>, Write<Orientation>)>>()
+        .for_each(|(vel, orientation)| {
+            let dir = vel.0.normalize();
+            let mut angle = na::angle(&dir, &na::Vector2::new(1.0, 0.0));
+            if dir.y < 0.0 {
+                angle = -angle;
+            }
+            orientation.0 = angle;
+        });
+}
+
+// Pushes every walking enemy away from the walking enemies around it and turns it along with
+// their heading. Neighbours come from the enemy grid, so they are where the last update left them.
+pub fn steer_crowd(world: &mut World, enemy_grid: &SpatialGrid, config: &CrowdConfig) {
+    let steering: Vec<_> = world
+        .matcher_with_entities::<All<(Read<Position>, Read<MoveTorwards>, Read<Enemy>)>>()
+        .filter(|(_, (_, _, enemy))| !enemy.flying)
+        .map(|(entity, (pos, _, _))| {
+            let mut separation = na::Vector2::zeros();
+            let mut alignment = na::Vector2::zeros();
+            let neighbours = enemy_grid
+                .query_radius(pos.0, config.radius)
+                .filter(|&(other, _)| other != entity)
+                .filter(|&(other, _)| {
+                    world
+                        .get_component::<Enemy>(other)
+                        .map_or(false, |enemy| !enemy.flying)
+                }).take(config.max_neighbours);
+            for (other, other_pos) in neighbours {
+                let offset = pos.0 - other_pos;
+                let distance = offset.norm();
+                // Enemies on the same spot drift apart through their different speeds
+                if distance > 0.0 {
+                    separation += offset / distance * (1.0 - distance / config.radius);
+                }
+                if let Some(target) = world.get_component::<MoveTorwards>(other) {
+                    alignment += target.heading;
+                }
+            }
+            let alignment = alignment
+                .try_normalize(std::f32::EPSILON)
+                .unwrap_or_else(na::Vector2::zeros);
+            let steering = separation * config.separation + alignment * config.alignment;
+            (entity, steering)
+        }).collect();
+    for (entity, steering) in steering {
+        if let Some(target) = world.get_component_mut::<MoveTorwards>(entity) {
+            target.steering = steering;
+        }
+    }
+}
+
+// Walking enemies follow the flow field towards their destination until they reach one of its
+// source cells, then walk straight for it, both blended with the crowd steering. Flying enemies
+// always go straight.
+pub fn follow_flow_fields(
+    world: &mut World,
+    flow: &FlowFields,
+    map: &TileMap,
+    goal: &Goal,
+    dt: DeltaTime,
+    now: SimTime,
+) {
+    world
+        .matcher::<All<(
+            Write<Position>,
+            Write<MoveTorwards>,
+            Read<Speed>,
+            Read<StatusEffects>,
+            Write<Flip>,
+            Read<Enemy>,
+        )>>().for_each(|(pos, target, speed, effects, flip, enemy)| {
+            let flow_dir = if enemy.flying {
+                None
+            } else {
+                flow.towards(target, goal).direction(map, pos.0)
+            };
+            // Enemies spawned on their destination, as on a level with one spawn line, keep
+            // their heading
+            let dir = flow_dir
+                .or_else(|| (target.destination - pos.0).try_normalize(std::f32::EPSILON))
+                .unwrap_or(target.heading);
+            let dir = (dir + target.steering)
+                .try_normalize(std::f32::EPSILON)
+                .unwrap_or(dir);
+            target.heading = dir;
+            let step = speed.0 * effects.speed_factor(now) * dt.0;
+            // Stops on the destination instead of stepping past it, see `update_destination`
+            if na::distance(&target.destination, &pos.0) <= step {
+                pos.0 = target.destination;
+            } else {
+                pos.0 += dir * step;
+            }
+            let angle = na::angle(&na::Vector2::new(1.0, 0.0), &dir);
+
+            *flip = if angle > PI / 2.0 {
+                Flip::Left
+            } else {
+                Flip::Right
+            };
+        });
+}
+
+pub fn update_destination(world: &mut World, sides: &Sides, goal: &Goal, rng: &mut SimRng) {
+    world
+        .matcher::<All<(Read<Position>, Write<MoveTorwards>)>>()
+        .for_each(|(pos, target)| {
+            let distance = na::distance(&target.destination, &pos.0);
+            if distance <= 1.0 && !goal.is_heading_here(target) {
+                *target = sides.next_destination(target, goal, rng);
+            }
+        });
+}
+
+// Removes the enemies that made it into the goal and returns how many did
+pub fn reach_goal(world: &mut World, goal: &Goal) -> usize {
+    let arrived: Vec<_> = world
+        .matcher_with_entities::<All<(Read<Position>, Read<MoveTorwards>, Read<Enemy>)>>()
+        .filter_map(|(entity, (pos, target, _))| {
+            if goal.is_heading_here(target) && na::distance(&goal.position, &pos.0) <= goal.radius {
+                Some(entity)
+            } else {
+                None
+            }
+        }).collect();
+    let count = arrived.len();
+    world.remove_entities(arrived);
+    count
+}
+
+// Damage over time from burning, which ignores armor and resistances. `kill_enemies` removes
+// the enemies it kills.
+pub fn burn_damage(world: &mut World, dt: DeltaTime, now: SimTime) {
+    world
+        .matcher::<All<(Write<Enemy>, Read<StatusEffects>)>>()
+        .for_each(|(enemy, effects)| {
+            enemy.health -= effects.burn_damage_per_second(now) * dt.0;
+        });
+}
+
+// Removes the dead enemies and returns the gold they were worth
+pub fn kill_enemies(world: &mut World) -> u32 {
+    let mut gold = 0;
+    let dead_enemies: Vec<_> = world
+        .matcher_with_entities::<All<(Read<Enemy>,)>>()
+        .filter_map(|(entity, (enemy,))| {
+            if enemy.health <= 0.0 {
+                gold += enemy.gold;
+                Some(entity)
+            } else {
+                None
+            }
+        }).collect();
+    world.remove_entities(dead_enemies);
+    gold
+}
+
+pub fn animate_explosion(world: &mut World, dt: DeltaTime) {
+    // Seconds every explosion takes to reach its `max_radius`
+    const EXPANSION_TIME: f32 = 1.0;
+    world
+        .matcher::<All<(Write<Explosion>, Read<Position>)>>()
+        .for_each(|(explosion, _)| {
+            explosion.radius += explosion.max_radius / EXPANSION_TIME * dt.0;
+        });
+    let entities: Vec<_> = world
+        .matcher_with_entities::<All<(Write<Explosion>,)>>()
+        .filter_map(|(entity, (explosion,))| {
+            if explosion.radius >= explosion.max_radius {
+                Some(entity)
+            } else {
+                None
+            }
+        }).collect();
+    world.remove_entities(entities);
+}
+
+// Damages the enemies that damaging explosions reached since the last tick, each one only once
+// per explosion. `enemy_grid` has to be up to date with the enemy positions.
+pub fn explosion_damage(world: &mut World, enemy_grid: &SpatialGrid) {
+    let mut hits = Vec::new();
+    world
+        .matcher::<All<(Read<Explosion>, Read<Position>, Write<AreaDamage>)>>()
+        .for_each(|(explosion, pos, area_damage)| {
+            for (enemy, enemy_pos) in enemy_grid.query_radius(pos.0, explosion.radius) {
+                if area_damage.hit.contains(&enemy) {
+                    continue;
+                }
+                area_damage.hit.push(enemy);
+                let distance = na::distance(&pos.0, &enemy_pos);
+                hits.push((
+                    enemy,
+                    area_damage.at_distance(distance, explosion.max_radius),
+                ));
+            }
+        });
+    for (enemy, damage) in hits {
+        if let Some(enemy) = world.get_component_mut::<Enemy>(enemy) {
+            enemy.take_hit(damage, DamageType::Splash);
+        }
+    }
+}
+
+pub fn kill_entities(world: &mut World, now: SimTime) {
+    let entities: Vec<_> = world
+        .matcher_with_entities::<All<(Read<TimeToLive>,)>>()
+        .filter_map(|(entity, (time,))| {
+            if now.duration_since(time.created) >= time.time_until_death {
+                Some(entity)
+            } else {
+                None
+            }
+        }).collect();
+    world.remove_entities(entities);
+}
+
+pub fn move_velocity(world: &mut World, dt: DeltaTime) {
+    world
+        .matcher::<All<(Write<Position>, Read<Velocity>)>>()
+        .for_each(|(pos, vel)| {
+            pos.0 += vel.0 * dt.0;
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        seeded_rng, spawn_random_enemies, AssetId, EnemyStats, Level, Resistances, SpawnLine,
+    };
+
+    const GRUNT: EnemyStats = EnemyStats {
+        asset: AssetId::Grunt,
+        scale: 1.0,
+        health: 100.0,
+        min_speed: 150.0,
+        max_speed: 200.0,
+        gold: 1,
+        armor: 0.0,
+        resistances: Resistances {
+            standard: 0.0,
+            homing: 0.0,
+            cluster: 0.0,
+            splash: 0.0,
+        },
+        flying: false,
+    };
+
+    #[test]
+    fn enemies_reach_their_waypoint_at_60_hz() {
+        let level = Level::default();
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let map = level.map(&sides, &goal);
+        let flow = FlowFields::new(&map, &sides, &goal);
+        let dt = DeltaTime(1.0 / 60.0);
+        let now = SimTime::default();
+        // Enough seeds that some approaches end between 1 pixel and one step away
+        for seed in 0..50 {
+            let mut rng = seeded_rng(seed);
+            let mut world = World::new();
+            spawn_random_enemies(&mut world, 1, &sides, &goal, &GRUNT, &mut rng);
+            for _ in 0..60 * 20 {
+                follow_flow_fields(&mut world, &flow, &map, &goal, dt, now);
+                update_destination(&mut world, &sides, &goal, &mut rng);
+            }
+            let legs = world
+                .matcher::<All<(Read<MoveTorwards>,)>>()
+                .map(|(target,)| target.legs)
+                .next();
+            assert!(
+                legs.unwrap() > 0,
+                "seed {} never reached its waypoint",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn enemies_spawned_on_their_destination_stay_put() {
+        let mut level = Level::default();
+        level.spawns = vec![SpawnLine {
+            from: (100.0, 300.0),
+            to: (100.0, 300.0),
+            count: 1,
+        }];
+        let sides = level.sides();
+        let goal = Goal::new(&level.goal);
+        let map = level.map(&sides, &goal);
+        let flow = FlowFields::new(&map, &sides, &goal);
+        let mut rng = seeded_rng(0);
+        let mut world = World::new();
+        spawn_random_enemies(&mut world, 1, &sides, &goal, &GRUNT, &mut rng);
+        let dt = DeltaTime(1.0 / 60.0);
+        follow_flow_fields(&mut world, &flow, &map, &goal, dt, SimTime::default());
+        world
+            .matcher::<All<(Read<Position>,)>>()
+            .for_each(|(pos,)| assert_eq!(pos.0, na::Point2::new(100.0, 300.0)));
+    }
+}
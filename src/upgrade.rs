@@ -0,0 +1,120 @@
+use crate::{
+    Config, ProjectileKind, Render, Shoot, Targeting, Tower, TowerConfig, UpgradeConfig,
+    UpgradeKind, Upgrades,
+};
+use pyro::*;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UpgradeError {
+    NoTower,
+    NotEnoughGold,
+    MaxLevel,
+    // Fragment upgrades for a tower that doesn't fire cluster missiles
+    NotApplicable,
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            UpgradeError::NoTower => "no tower selected",
+            UpgradeError::NotEnoughGold => "not enough gold",
+            UpgradeError::MaxLevel => "already at the maximum level",
+            UpgradeError::NotApplicable => "this tower doesn't fire cluster missiles",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+pub fn can_upgrade(tower: &TowerConfig, upgrade: UpgradeKind) -> bool {
+    match (upgrade, tower.projectile) {
+        (UpgradeKind::Fragments, ProjectileKind::Cluster { .. }) => true,
+        (UpgradeKind::Fragments, _) => false,
+        _ => true,
+    }
+}
+
+// The stats are always derived from the base stats of the tower kind, so upgrading never
+// accumulates rounding errors
+pub fn upgraded_shoot(
+    tower: &TowerConfig,
+    upgrades: &Upgrades,
+    config: &UpgradeConfig,
+    shoot: &mut Shoot,
+) {
+    shoot.damage = tower.damage * (1.0 + config.damage * upgrades.damage as f32);
+    let fire_rate = 1.0 + config.fire_rate * upgrades.fire_rate as f32;
+    let recover_ms = tower.recover_ms as f32 / fire_rate;
+    shoot.recover.recover = Duration::from_millis(recover_ms as u64);
+    if let ProjectileKind::Cluster { fragments, .. } = tower.projectile {
+        if let ProjectileKind::Cluster {
+            fragments: ref mut upgraded,
+            ..
+        } = shoot.projectile
+        {
+            *upgraded = fragments + config.fragments * upgrades.fragments as usize;
+        }
+    }
+}
+
+pub fn upgraded_targeting(
+    tower: &TowerConfig,
+    upgrades: &Upgrades,
+    config: &UpgradeConfig,
+    targeting: &mut Targeting,
+) {
+    targeting.range = tower.range * (1.0 + config.range * upgrades.range as f32);
+}
+
+pub fn upgraded_render(
+    tower: &TowerConfig,
+    upgrades: &Upgrades,
+    config: &UpgradeConfig,
+    render: &mut Render,
+) {
+    render.scale = tower.scale * (1.0 + config.scale * upgrades.total() as f32);
+}
+
+// Buys the next level of `upgrade` for the tower `entity` and applies it
+pub fn upgrade_tower(
+    world: &mut World,
+    entity: Entity,
+    upgrade: UpgradeKind,
+    gold: &mut u32,
+    config: &Config,
+) -> Result<(), UpgradeError> {
+    let kind = world
+        .get_component::<Tower>(entity)
+        .ok_or(UpgradeError::NoTower)?
+        .kind;
+    let tower = &config.towers[kind];
+    if !can_upgrade(tower, upgrade) {
+        return Err(UpgradeError::NotApplicable);
+    }
+    let upgrades = world
+        .get_component_mut::<Upgrades>(entity)
+        .ok_or(UpgradeError::NoTower)?;
+    let level = upgrades.level(upgrade);
+    if level >= config.upgrades.max_level {
+        return Err(UpgradeError::MaxLevel);
+    }
+    let cost = config.upgrades.cost(level);
+    if *gold < cost {
+        return Err(UpgradeError::NotEnoughGold);
+    }
+    *gold -= cost;
+    *upgrades.level_mut(upgrade) += 1;
+    let upgrades = *upgrades;
+
+    if let Some(shoot) = world.get_component_mut::<Shoot>(entity) {
+        upgraded_shoot(tower, &upgrades, &config.upgrades, shoot);
+    }
+    if let Some(targeting) = world.get_component_mut::<Targeting>(entity) {
+        upgraded_targeting(tower, &upgrades, &config.upgrades, targeting);
+    }
+    if let Some(render) = world.get_component_mut::<Render>(entity) {
+        upgraded_render(tower, &upgrades, &config.upgrades, render);
+    }
+    Ok(())
+}
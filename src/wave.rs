@@ -0,0 +1,156 @@
+use crate::{
+    spawn_random_enemies, Enemy, EnemyConfig, EnemyStats, Goal, Sides, SimRng, SimTime, WaveConfig,
+};
+use pyro::*;
+use std::time::Duration;
+
+enum WaveState {
+    // Waiting `delay_ms` before the next wave starts
+    Delay {
+        start: SimTime,
+    },
+    // Indices into `WaveSpawner::enemies` of the enemies left to spawn in this wave, in reverse
+    // spawn order
+    Spawning {
+        queue: Vec<usize>,
+        next_spawn: SimTime,
+    },
+    // Everything is spawned, waiting for the wave to be cleared
+    Fighting,
+}
+
+pub struct WaveSpawner {
+    pub waves: Vec<WaveConfig>,
+    pub growth: f32,
+    pub enemies: Vec<EnemyConfig>,
+    // The current wave, starting at 1. 0 until the first wave starts.
+    pub wave: usize,
+    state: WaveState,
+}
+impl WaveSpawner {
+    pub fn new(waves: Vec<WaveConfig>, growth: f32, enemies: Vec<EnemyConfig>) -> Self {
+        WaveSpawner {
+            waves,
+            growth,
+            enemies,
+            wave: 0,
+            state: WaveState::Delay {
+                start: SimTime::default(),
+            },
+        }
+    }
+
+    // Waves past the configured ones repeat the last wave, scaled up by `growth` each time
+    pub fn wave_config(&self, wave: usize) -> WaveConfig {
+        let idx = wave.saturating_sub(1);
+        let last = self.waves.len() - 1;
+        let mut config = self.waves[idx.min(last)].clone();
+        if idx > last {
+            let scale = self.growth.powi((idx - last) as i32);
+            config.health_multiplier *= scale;
+            for group in &mut config.groups {
+                group.count = (group.count as f32 * scale).ceil() as usize;
+            }
+        }
+        config
+    }
+
+    // Waves whose enemies have all been spawned and killed
+    pub fn cleared(&self) -> usize {
+        match self.state {
+            WaveState::Delay { .. } => self.wave,
+            _ => self.wave - 1,
+        }
+    }
+
+    fn enemy_kind(&self, name: &str) -> Option<usize> {
+        self.enemies.iter().position(|enemy| enemy.name == name)
+    }
+
+    fn stats(&self, kind: usize, config: &WaveConfig) -> EnemyStats {
+        let enemy = &self.enemies[kind];
+        EnemyStats {
+            asset: enemy.asset,
+            scale: enemy.scale,
+            health: enemy.health * config.health_multiplier,
+            min_speed: config.min_speed * enemy.speed,
+            max_speed: config.max_speed * enemy.speed,
+            gold: enemy.gold,
+            armor: enemy.armor,
+            resistances: enemy.resistances,
+            flying: enemy.flying,
+        }
+    }
+
+    pub fn spawn_enemies(
+        &mut self,
+        world: &mut World,
+        sides: &Sides,
+        goal: &Goal,
+        rng: &mut SimRng,
+        now: SimTime,
+    ) {
+        match self.state {
+            WaveState::Delay { start } => {
+                let next = self.wave_config(self.wave + 1);
+                if now.duration_since(start) < Duration::from_millis(next.delay_ms) {
+                    return;
+                }
+                self.wave += 1;
+                // `Level::validate` checks the waves with `Config::validate_waves`
+                let queue = next
+                    .groups
+                    .iter()
+                    .rev()
+                    .map(|group| {
+                        let kind = self
+                            .enemy_kind(&group.enemy)
+                            .expect("wave groups are validated against the config");
+                        (kind, group.count)
+                    }).flat_map(|(kind, count)| (0..count).map(move |_| kind))
+                    .collect();
+                self.state = WaveState::Spawning {
+                    queue,
+                    next_spawn: now,
+                };
+            }
+            WaveState::Fighting => {
+                let living_enemies = world.matcher::<All<(Read<Enemy>,)>>().count();
+                if living_enemies == 0 {
+                    self.state = WaveState::Delay { start: now };
+                }
+                return;
+            }
+            WaveState::Spawning { .. } => {}
+        }
+
+        let config = self.wave_config(self.wave);
+        let interval = Duration::from_millis(config.spawn_interval_ms);
+        // Runs of the same kind among the enemies due this tick, each one is spawned in one batch
+        let mut batches: Vec<(usize, usize)> = Vec::new();
+        if let WaveState::Spawning {
+            ref mut queue,
+            ref mut next_spawn,
+        } = self.state
+        {
+            while *next_spawn <= now {
+                let kind = match queue.pop() {
+                    Some(kind) => kind,
+                    None => break,
+                };
+                match batches.last_mut() {
+                    Some((last, count)) if *last == kind => *count += 1,
+                    _ => batches.push((kind, 1)),
+                }
+                next_spawn.0 += interval;
+            }
+            if queue.is_empty() {
+                self.state = WaveState::Fighting;
+            }
+        }
+        for (kind, count) in batches {
+            let stats = self.stats(kind, &config);
+            spawn_random_enemies(world, count, sides, goal, &stats, rng);
+        }
+    }
+}